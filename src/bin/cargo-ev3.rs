@@ -0,0 +1,16 @@
+use ev3_runner::{
+    cargo_ev3,
+    cli::{CargoCli, Parser},
+    setup_logging,
+};
+use std::process::ExitCode;
+use tracing::error;
+
+fn main() -> anyhow::Result<ExitCode> {
+    let CargoCli::Ev3(config) = CargoCli::parse();
+    setup_logging(config.verbose);
+
+    let exit_code = cargo_ev3(config).inspect_err(|e| error!("cargo ev3 failed: {e}"))?;
+
+    Ok(exit_code)
+}
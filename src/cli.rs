@@ -1,5 +1,30 @@
+use crate::{
+    protocol::HashAlgorithm,
+    server::Cidr,
+    transport::{DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_MAX_WINDOW_LOG},
+};
 pub use clap::Parser;
-use std::path::PathBuf;
+use std::{net::IpAddr, path::PathBuf, time::Duration};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1:6767";
+pub const DEFAULT_PASSWORD: &str = "maker";
+pub const DEFAULT_DISCOVERY_PORT: u16 = 6767;
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+pub const MIN_COMPRESSION_LEVEL: i32 = -7;
+/// Highest level the server can decode with its default window limit, 20 to 22 use larger
+/// windows
+pub const MAX_COMPRESSION_LEVEL: i32 = 19;
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+pub const DEFAULT_HANDSHAKE_TIMEOUT: u64 = 20;
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 60;
+pub const DEFAULT_KEEPALIVE: u64 = 15;
+pub const DEFAULT_MAX_LOGIN_FAILURES: u32 = 5;
+pub const DEFAULT_LOGIN_BACKOFF: u64 = 1;
+pub const DEFAULT_LOCKOUT: u64 = 300;
+pub const DEFAULT_AUDIT_LOG_MAX_SIZE: u64 = 1024 * 1024;
+pub const DEFAULT_AUDIT_LOG_KEEP: u32 = 3;
+pub const DEFAULT_KEEP_VERSIONS: usize = 3;
+pub const DEFAULT_GC_UNUSED_DAYS: u32 = 30;
 
 #[derive(Debug, clap::Parser)]
 #[command(
@@ -58,6 +83,68 @@ pub enum Action {
                             If the file hash matches what's already on the server, upload is skipped."
     )]
     Run(ClientArgs),
+    /// Show the hardware status of the robot
+    #[command(
+        long_about = "Query the battery voltage and the motors and sensors connected to the robot.\n\
+                            The information is read from the ev3dev sysfs on the server."
+    )]
+    Status(StatusArgs),
+    /// Delete a file on the server
+    #[command(long_about = "Delete a file on the server.\n\
+                            The account has to be allowed to delete files.")]
+    Delete(DeleteArgs),
+    /// List the kept versions of a file on the server
+    #[command(
+        long_about = "List the deployed version of a file and the earlier versions the server kept,\n\
+                            newest first, with their hash, size and age."
+    )]
+    History(HistoryArgs),
+    /// Deploy an earlier version of a file again
+    #[command(
+        long_about = "Replace a file on the server with an earlier version it kept, in one step.\n\
+                            Restores the newest kept version, or the one given with --to. The replaced\n\
+                            version is kept in turn, so a rollback can be undone. The account has to be\n\
+                            allowed to upload files."
+    )]
+    Rollback(RollbackArgs),
+    /// Remove files on the server that weren't used for a while
+    #[command(
+        long_about = "Remove the files on the server that weren't uploaded, run or found up to date for\n\
+                            the given number of days, least recently used first, together with old kept versions.\n\
+                            Only the files under the account's root are removed. The account has to be allowed\n\
+                            to delete files."
+    )]
+    Gc(GcArgs),
+    /// Replace the server binary
+    #[command(
+        long_about = "Upload a new ev3-runner binary, which replaces the one of the server.\n\
+                            The server checks that the binary runs, keeps the old one as <binary>.old and\n\
+                            restarts with the new one. Works across versions, the account has to be\n\
+                            allowed to update the server."
+    )]
+    UpdateServer(UpdateServerArgs),
+    /// Find servers on the local network
+    #[command(
+        long_about = "Broadcast a discovery request on the local network and list all servers that answer.\n\
+                            The listed names can be used with --host instead of an address."
+    )]
+    Discover(DiscoverArgs),
+    /// Upload and run a binary built by cargo
+    #[command(
+        long_about = "Upload and run a binary, taking the arguments the way cargo passes them to a target runner.\n\
+                            Set `runner = \"ev3-runner client runner\"` for the EV3 target in .cargo/config.toml\n\
+                            to deploy with `cargo run` and `cargo test`. Options not given on the command line\n\
+                            are taken from the environment or the ev3-runner.toml config file.\n\
+                            Exits with the exit code of the remote program."
+    )]
+    Runner(RunnerArgs),
+    /// Upload and run test binaries and report the results
+    #[command(
+        long_about = "Upload and run test binaries built with `cargo test --no-run` and collect the results.\n\
+                            Prints a summary of all passed, failed and ignored tests, optionally writes a\n\
+                            JUnit XML report and exits with 101 if any test failed."
+    )]
+    Test(TestArgs),
 }
 
 #[derive(Debug, clap::Args)]
@@ -66,45 +153,449 @@ pub struct ClientArgs {
     #[arg(value_name = "FILE")]
     pub filepath: PathBuf,
 
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Where to save the file on the server
+    #[clap(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Remote file path (default: same filename as local)"
+    )]
+    pub remote_path: Option<PathBuf>,
+
+    /// Use brickrun
+    #[clap(short, long, help = "If the program should be started using brickrun")]
+    pub brickrun: bool,
+
+    #[command(flatten)]
+    pub compression: CompressionArgs,
+
+    /// Redeploy whenever the local file changes
+    #[clap(
+        short,
+        long,
+        help = "Watch the local file and re-upload (and restart) it whenever it changes"
+    )]
+    pub watch: bool,
+
+    /// Debounce time for watch mode
+    #[clap(
+        long,
+        default_value = "500",
+        value_name = "MILLISECONDS",
+        help = "How long the file has to stay unchanged before redeploying in watch mode"
+    )]
+    pub debounce: u64,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print log lines and the program output, or one JSON event per line"
+    )]
+    pub output: OutputFormat,
+
+    /// Arguments passed to the program
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct CompressionArgs {
+    /// If compression should be used to send the file
+    #[clap(short, long, help = "If compression should be used to send the file")]
+    pub compression: bool,
+
+    /// Decide per file whether compression pays off
+    #[clap(
+        long,
+        conflicts_with = "compression",
+        help = "Compress only if a sample of the file compresses well enough for the link speed"
+    )]
+    pub auto_compression: bool,
+
+    /// zstd compression level
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(i32)
+            .range(i64::from(MIN_COMPRESSION_LEVEL)..=i64::from(MAX_COMPRESSION_LEVEL)),
+        help = "zstd compression level from -7 (fastest) to 19 (smallest), implies --compression (default: 3)"
+    )]
+    pub compression_level: Option<i32>,
+
+    /// Threads used for compression
+    #[clap(
+        long,
+        value_name = "THREADS",
+        help = "Compress on this many background threads (default: 0, compress on the main thread)"
+    )]
+    pub compression_threads: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable output
+    Human,
+    /// One JSON object per line on stdout
+    Json,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print a table or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DeleteArgs {
+    /// Path of the file on the server
+    #[arg(value_name = "REMOTE_PATH")]
+    pub remote_path: PathBuf,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print log lines or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct HistoryArgs {
+    /// Path of the file on the server
+    #[arg(value_name = "REMOTE_PATH")]
+    pub remote_path: PathBuf,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print a list or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RollbackArgs {
+    /// Path of the file on the server
+    #[arg(value_name = "REMOTE_PATH")]
+    pub remote_path: PathBuf,
+
+    /// Version to restore
+    #[clap(
+        long,
+        value_name = "HASH",
+        help = "Restore the kept version whose hash starts with HASH instead of the newest one"
+    )]
+    pub to: Option<String>,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print log lines or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct GcArgs {
+    /// Days since the last use
+    #[clap(
+        long,
+        default_value_t = DEFAULT_GC_UNUSED_DAYS,
+        value_name = "DAYS",
+        help = "Remove deployed files that weren't used for DAYS"
+    )]
+    pub unused_days: u32,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print a list or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct UpdateServerArgs {
+    /// New server binary, built for the robot
+    #[arg(value_name = "BINARY")]
+    pub binary: PathBuf,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print log lines or JSON"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ConnectionArgs {
     /// Server address and port
     #[clap(
         long,
-        default_value = "127.0.0.1:6767",
+        default_value = DEFAULT_HOST,
         value_name = "HOST:PORT",
-        help = "Server address in format IP:PORT"
+        help = "Server address in format IP:PORT, or the name of a discovered robot"
     )]
     pub host: String,
 
-    /// Where to save the file on the server
+    /// UDP port used to look up robot names
+    #[clap(
+        long,
+        default_value_t = DEFAULT_DISCOVERY_PORT,
+        value_name = "PORT",
+        help = "UDP port used to resolve robot names given to --host"
+    )]
+    pub discovery_port: u16,
+
+    /// Account name
     #[clap(
         short,
         long,
-        value_name = "PATH",
-        help = "Remote file path (default: same filename as local)"
+        value_name = "USER",
+        help = "Account to log in with, if the server has accounts"
     )]
-    pub remote_path: Option<PathBuf>,
+    pub user: Option<String>,
 
     /// Password for authentication
     #[clap(
         short,
         long,
-        default_value = "maker",
+        default_value = DEFAULT_PASSWORD,
         value_name = "PASSWORD",
         help = "Password to authenticate with the server"
     )]
     pub password: String,
 
+    /// Hash algorithm
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        help = "Hash algorithm used to skip uploads (default: the one the server prefers)"
+    )]
+    pub hash: Option<HashAlgorithm>,
+
+    #[command(flatten)]
+    pub timeouts: TimeoutArgs,
+}
+
+/// Timeouts of a connection to the server, in seconds, 0 waits forever
+#[derive(Debug, Clone, clap::Args)]
+pub struct TimeoutArgs {
+    /// Connect timeout
+    #[clap(
+        long,
+        default_value_t = DEFAULT_CONNECT_TIMEOUT,
+        value_name = "SECONDS",
+        help = "How long to wait for the server to accept the connection"
+    )]
+    pub connect_timeout: u64,
+
+    /// Handshake timeout
+    #[clap(
+        long,
+        default_value_t = DEFAULT_HANDSHAKE_TIMEOUT,
+        value_name = "SECONDS",
+        help = "How long the server may take to answer the version check and the request"
+    )]
+    pub handshake_timeout: u64,
+
+    /// Idle transfer timeout
+    #[clap(
+        long,
+        default_value_t = DEFAULT_IDLE_TIMEOUT,
+        value_name = "SECONDS",
+        help = "How long an upload or status query may stall (not applied to running programs)"
+    )]
+    pub idle_timeout: u64,
+
+    /// Keepalive
+    #[clap(
+        long,
+        default_value_t = DEFAULT_KEEPALIVE,
+        value_name = "SECONDS",
+        help = "Idle time before TCP keepalive probes check that the server is still there"
+    )]
+    pub keepalive: u64,
+}
+
+/// Timeout given in seconds on the command line, 0 means none
+pub(crate) fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Connection options that fall back to the environment and the config file
+#[derive(Debug, clap::Args)]
+pub struct EnvConnectionArgs {
+    /// Server address and port
+    #[clap(
+        long,
+        env = "EV3_RUNNER_HOST",
+        value_name = "HOST:PORT",
+        help = "Server address in format IP:PORT, or the name of a discovered robot"
+    )]
+    pub host: Option<String>,
+
+    /// Account name
+    #[clap(
+        long,
+        env = "EV3_RUNNER_USER",
+        value_name = "USER",
+        help = "Account to log in with, if the server has accounts"
+    )]
+    pub user: Option<String>,
+
+    /// Password for authentication
+    #[clap(
+        short,
+        long,
+        env = "EV3_RUNNER_PASSWORD",
+        hide_env_values = true,
+        value_name = "PASSWORD",
+        help = "Password to authenticate with the server"
+    )]
+    pub password: Option<String>,
+
+    /// Hash algorithm
+    #[clap(
+        long,
+        value_enum,
+        env = "EV3_RUNNER_HASH",
+        value_name = "ALGORITHM",
+        help = "Hash algorithm used to skip uploads (default: the one the server prefers)"
+    )]
+    pub hash: Option<HashAlgorithm>,
+
+    #[command(flatten)]
+    pub timeouts: TimeoutArgs,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RunnerArgs {
+    #[command(flatten)]
+    pub connection: EnvConnectionArgs,
+
     /// Use brickrun
     #[clap(short, long, help = "If the program should be started using brickrun")]
     pub brickrun: bool,
 
-    /// If compression should be used to send the file
-    #[clap(short, long, help = "If compression should be used to send the file")]
-    pub compression: bool,
+    #[command(flatten)]
+    pub compression: CompressionArgs,
+
+    /// Path to the binary built by cargo
+    #[arg(value_name = "BINARY")]
+    pub binary: PathBuf,
+
+    /// Arguments passed to the program
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "ARGS"
+    )]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TestArgs {
+    /// Test binaries to run
+    #[arg(value_name = "BINARY", required = true)]
+    pub binaries: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub connection: EnvConnectionArgs,
+
+    /// Write a JUnit XML report
+    #[clap(long, value_name = "FILE", help = "Write a JUnit XML report to FILE")]
+    pub junit: Option<PathBuf>,
+
+    /// Use brickrun
+    #[clap(short, long, help = "If the tests should be started using brickrun")]
+    pub brickrun: bool,
+
+    #[command(flatten)]
+    pub compression: CompressionArgs,
+
+    /// Arguments passed to every test binary, e.g. test name filters
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DiscoverArgs {
+    /// UDP port the servers answer discovery requests on
+    #[clap(
+        long,
+        default_value = "6767",
+        value_name = "PORT",
+        help = "UDP port the servers answer discovery requests on"
+    )]
+    pub discovery_port: u16,
+
+    /// How long to wait for answers
+    #[clap(
+        short,
+        long,
+        default_value = "1000",
+        value_name = "MILLISECONDS",
+        help = "How long to wait for answers in milliseconds"
+    )]
+    pub timeout: u64,
+
+    /// Output format
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        help = "Print a table or one JSON object per server"
+    )]
+    pub output: OutputFormat,
 }
 
 #[derive(Debug, clap::Args)]
 pub struct Server {
+    #[command(subcommand)]
+    pub command: Option<ServerCommand>,
+
     /// Port to listen on
     #[clap(
         short,
@@ -115,13 +606,432 @@ pub struct Server {
     )]
     pub server_port: u16,
 
+    /// Address to listen on
+    #[clap(
+        long,
+        default_value = "0.0.0.0",
+        value_name = "ADDR",
+        help = "Address of the interface to listen on, e.g. the one of the USB network (default: all)"
+    )]
+    pub bind: IpAddr,
+
+    /// Addresses allowed to connect
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "CIDR",
+        help = "Only accept connections from these addresses or blocks, like 192.168.0.0/24 (default: all)"
+    )]
+    pub allow: Vec<Cidr>,
+
+    /// Addresses refused
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "CIDR",
+        help = "Never accept connections from these addresses or blocks, even if they are allowed"
+    )]
+    pub deny: Vec<Cidr>,
+
     /// Server password
     #[clap(
         short,
         long,
-        default_value = "maker",
+        env = "EV3_RUNNER_PASSWORD",
+        default_value = DEFAULT_PASSWORD,
         value_name = "PASSWORD",
         help = "Password required for client authentication"
     )]
     pub password: String,
+
+    /// Accounts file
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "TOML file with named accounts, which replace the password (see README)"
+    )]
+    pub accounts: Option<PathBuf>,
+
+    /// Directory to store uploaded files in
+    #[clap(
+        long,
+        default_value = ".",
+        value_name = "PATH",
+        help = "Directory uploaded files are stored in and run from"
+    )]
+    pub root: PathBuf,
+
+    /// Root of the sysfs tree to read the hardware status from
+    #[clap(
+        long,
+        default_value = "/sys",
+        value_name = "PATH",
+        help = "Root of the sysfs tree used for status queries"
+    )]
+    pub sysfs_root: PathBuf,
+
+    /// Name announced to discovery requests
+    #[clap(
+        short,
+        long,
+        value_name = "NAME",
+        help = "Robot name announced to discovery requests (default: hostname)"
+    )]
+    pub name: Option<String>,
+
+    /// UDP port for discovery requests
+    #[clap(
+        long,
+        default_value = "6767",
+        value_name = "PORT",
+        help = "UDP port to answer discovery requests on"
+    )]
+    pub discovery_port: u16,
+
+    /// Disable the discovery responder
+    #[clap(long, help = "Don't answer discovery requests")]
+    pub no_discovery: bool,
+
+    /// Accepted hash algorithms
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "blake3,sha256,xxhash",
+        value_name = "ALGORITHMS",
+        help = "Hash algorithms clients may use to skip uploads, most preferred first"
+    )]
+    pub hash_algorithms: Vec<HashAlgorithm>,
+
+    /// Largest message accepted from clients
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_MESSAGE_SIZE,
+        value_name = "BYTES",
+        help = "Largest message in bytes accepted from clients (at most 16 MiB)"
+    )]
+    pub max_message_size: usize,
+
+    /// Largest file chunk accepted from clients
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_CHUNK_SIZE,
+        value_name = "BYTES",
+        help = "Largest chunk of an uploaded file in bytes (32 KiB to 16 MiB)"
+    )]
+    pub max_chunk_size: usize,
+
+    /// Largest zstd window accepted from clients
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_WINDOW_LOG,
+        value_name = "LOG",
+        help = "Largest zstd window of a compressed upload as a power of two (10 to 27)"
+    )]
+    pub max_window_log: u32,
+
+    /// Handshake timeout
+    #[clap(
+        long,
+        default_value_t = DEFAULT_HANDSHAKE_TIMEOUT,
+        value_name = "SECONDS",
+        help = "How long a client may take from connecting to sending its version, and again to sending its request, 0 waits forever"
+    )]
+    pub handshake_timeout: u64,
+
+    /// Idle transfer timeout
+    #[clap(
+        long,
+        default_value_t = DEFAULT_IDLE_TIMEOUT,
+        value_name = "SECONDS",
+        help = "How long an upload or the program output may stall, 0 waits forever"
+    )]
+    pub idle_timeout: u64,
+
+    /// Keepalive
+    #[clap(
+        long,
+        default_value_t = DEFAULT_KEEPALIVE,
+        value_name = "SECONDS",
+        help = "Idle time before TCP keepalive probes check that the client is still there, 0 disables them"
+    )]
+    pub keepalive: u64,
+
+    /// Wrong passwords until a client is locked out
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_LOGIN_FAILURES,
+        value_name = "COUNT",
+        help = "Wrong passwords in a row until a client address is locked out, 0 disables the limit"
+    )]
+    pub max_login_failures: u32,
+
+    /// Backoff after a wrong password
+    #[clap(
+        long,
+        default_value_t = DEFAULT_LOGIN_BACKOFF,
+        value_name = "SECONDS",
+        help = "Wait after the first wrong password, doubled for every further one"
+    )]
+    pub login_backoff: u64,
+
+    /// Lockout duration
+    #[clap(
+        long,
+        default_value_t = DEFAULT_LOCKOUT,
+        value_name = "SECONDS",
+        help = "How long a client address is locked out after too many wrong passwords"
+    )]
+    pub lockout: u64,
+
+    /// Audit log
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Append a JSON line describing every connection to FILE"
+    )]
+    pub audit_log: Option<PathBuf>,
+
+    /// Size at which the audit log is rotated
+    #[clap(
+        long,
+        default_value_t = DEFAULT_AUDIT_LOG_MAX_SIZE,
+        value_name = "BYTES",
+        help = "Size the audit log may grow to before it is rotated"
+    )]
+    pub audit_log_max_size: u64,
+
+    /// Rotated audit logs to keep
+    #[clap(
+        long,
+        default_value_t = DEFAULT_AUDIT_LOG_KEEP,
+        value_name = "COUNT",
+        help = "Rotated audit logs kept besides the current one"
+    )]
+    pub audit_log_keep: u32,
+
+    /// Versions kept per file
+    #[clap(
+        long,
+        default_value_t = DEFAULT_KEEP_VERSIONS,
+        value_name = "COUNT",
+        help = "Earlier versions of every deployed file kept for `client rollback`, 0 keeps none"
+    )]
+    pub keep_versions: usize,
+
+    /// Storage quota
+    #[clap(
+        long,
+        value_name = "BYTES",
+        help = "Space the deployed files and their kept versions may take up; larger uploads are refused"
+    )]
+    pub quota: Option<u64>,
+
+    /// Automatic garbage collection
+    #[clap(
+        long,
+        value_name = "DAYS",
+        help = "When an upload doesn't fit, remove deployed files unused for DAYS, least recently used first, until it does"
+    )]
+    pub auto_gc: Option<u32>,
+
+    /// Refuse updates
+    #[clap(
+        long,
+        help = "Don't let clients replace the server binary with `client update-server`"
+    )]
+    pub no_update: bool,
+
+    /// Run in the background
+    #[clap(
+        long,
+        help = "Start the server in the background and return once it is listening"
+    )]
+    pub daemon: bool,
+
+    /// Pidfile
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Write the process id to FILE while the server runs"
+    )]
+    pub pidfile: Option<PathBuf>,
+
+    /// Log file of the daemon
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "daemon",
+        help = "Append the log of the daemon to FILE (default: discard it)"
+    )]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ServerCommand {
+    /// Install the server as a systemd service
+    #[command(
+        long_about = "Write a systemd unit that starts the server with the options given before `install-service`.\n\
+                            With --socket-activation, systemd listens on the port and starts the server\n\
+                            on the first connection."
+    )]
+    InstallService(Box<InstallServiceArgs>),
 }
+
+#[derive(Debug, clap::Args)]
+pub struct InstallServiceArgs {
+    /// Directory to write the units to
+    #[clap(
+        long,
+        default_value = "/etc/systemd/system",
+        value_name = "DIR",
+        help = "Directory the units are written to"
+    )]
+    pub unit_dir: PathBuf,
+
+    /// Name of the units
+    #[clap(
+        long,
+        default_value = "ev3-runner",
+        value_name = "NAME",
+        help = "Name of the units, without .service or .socket"
+    )]
+    pub unit_name: String,
+
+    /// Environment file with the password
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "File only root can read the password is written to, instead of into the unit (default: /etc/default/NAME)"
+    )]
+    pub env_file: Option<PathBuf>,
+
+    /// Socket activation
+    #[clap(
+        long,
+        help = "Also write a .socket unit, so systemd starts the server on the first connection"
+    )]
+    pub socket_activation: bool,
+
+    /// User to run as
+    #[clap(
+        long,
+        value_name = "USER",
+        help = "User the server runs as (default: root)"
+    )]
+    pub user: Option<String>,
+
+    /// Print instead of writing
+    #[clap(long, help = "Print the units instead of writing them")]
+    pub print: bool,
+}
+
+/// Entry point of the `cargo-ev3` binary, invoked by cargo as `cargo ev3`
+#[derive(Debug, clap::Parser)]
+#[command(name = "cargo", bin_name = "cargo")]
+pub enum CargoCli {
+    /// Build for the EV3 and upload or run the result
+    #[command(
+        version,
+        long_about = "Cross-compile the project for the EV3 and deploy the built binary with ev3-runner.\n\
+                      The connection options are taken from the command line, the environment or the\n\
+                      ev3-runner.toml config file, like for `ev3-runner client runner`."
+    )]
+    Ev3(CargoEv3),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CargoEv3 {
+    #[clap(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Increase logging verbosity (-v: INFO, -vv: DEBUG, -vvv: TRACE)"
+    )]
+    pub verbose: u8,
+    #[command(subcommand)]
+    pub action: CargoAction,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum CargoAction {
+    /// Build and upload a binary
+    Upload(CargoArgs),
+    /// Build, upload and run a binary
+    Run(CargoArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CargoArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    #[command(flatten)]
+    pub connection: EnvConnectionArgs,
+
+    /// Where to save the file on the server
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Remote file path (default: name of the binary)"
+    )]
+    pub remote_path: Option<PathBuf>,
+
+    /// Use brickrun
+    #[clap(short, long, help = "If the program should be started using brickrun")]
+    pub brickrun: bool,
+
+    #[command(flatten)]
+    pub compression: CompressionArgs,
+
+    /// Arguments passed to the program
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+/// Options forwarded to `cargo build`
+#[derive(Debug, clap::Args)]
+pub struct BuildArgs {
+    /// Build only the given binary
+    #[clap(long, value_name = "NAME", conflicts_with = "example")]
+    pub bin: Option<String>,
+
+    /// Build only the given example
+    #[clap(long, value_name = "NAME")]
+    pub example: Option<String>,
+
+    /// Package to build
+    #[clap(long, value_name = "SPEC")]
+    pub package: Option<String>,
+
+    /// Build in release mode
+    #[clap(short, long, conflicts_with = "profile")]
+    pub release: bool,
+
+    /// Build with the given profile
+    #[clap(long, value_name = "PROFILE-NAME")]
+    pub profile: Option<String>,
+
+    /// Features to activate
+    #[clap(short = 'F', long, value_name = "FEATURES")]
+    pub features: Vec<String>,
+
+    /// Activate all available features
+    #[clap(long)]
+    pub all_features: bool,
+
+    /// Do not activate the `default` feature
+    #[clap(long)]
+    pub no_default_features: bool,
+
+    /// Path to Cargo.toml
+    #[clap(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Target triple to build for
+    #[clap(long, value_name = "TRIPLE", default_value = EV3_TARGET)]
+    pub target: String,
+}
+
+pub const EV3_TARGET: &str = "armv5te-unknown-linux-musleabi";
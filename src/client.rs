@@ -1,22 +1,289 @@
+#[cfg(feature = "async")]
+mod async_client;
+mod cargo;
 mod clientsession;
+mod compression;
+mod discovery;
+mod ev3client;
+mod events;
+mod gc;
+mod hash_cache;
+mod history;
+mod progress_bar;
+mod remote_test;
+mod run_handle;
+mod runner;
+mod status;
+mod update;
 mod validation;
 mod version;
+mod watch;
 
 use crate::{
-    cli::{Action, Client},
-    client::clientsession::ClientSession,
-    protocol::{self},
+    cli::{
+        Action, Client, ClientArgs, CompressionArgs, ConnectionArgs, DEFAULT_COMPRESSION_LEVEL,
+        OutputFormat, seconds,
+    },
+    protocol::ExitStatus,
 };
-use clientsession::ClientError;
+use progress_bar::ProgressBar;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+use tracing::info;
+
+#[cfg(feature = "async")]
+pub use async_client::{AsyncEv3Client, AsyncRunHandle};
+pub use cargo::cargo_ev3;
+pub use clientsession::ClientError;
+pub use compression::CompressionMode;
+pub use discovery::{DiscoveredServer, discover};
+pub use ev3client::{Ev3Client, Ev3ClientBuilder, UploadReport};
+pub use events::ClientEvent;
+pub use run_handle::{Output, OutputReader, RunHandle, RunOptions};
+
+pub fn client(config: Client) -> Result<ExitCode, ClientError> {
+    let output = match &config.action {
+        Action::Upload(args) | Action::Run(args) => args.output,
+        Action::Status(args) => args.output,
+        Action::Delete(args) => args.output,
+        Action::History(args) => args.output,
+        Action::Rollback(args) => args.output,
+        Action::Gc(args) => args.output,
+        Action::UpdateServer(args) => args.output,
+        Action::Discover(args) => args.output,
+        Action::Runner(_) | Action::Test(_) => OutputFormat::Human,
+    };
+
+    let result = run_action(config.action);
+    if let (OutputFormat::Json, Err(e)) = (output, &result) {
+        print_json(&json!({ "event": "error", "message": e.to_string() }));
+    }
+    result
+}
+
+fn run_action(action: Action) -> Result<ExitCode, ClientError> {
+    match action {
+        Action::Upload(args) if args.watch => watch::watch(&args, None)?,
+        Action::Upload(args) => {
+            let remote_path = remote_path(&args)?;
+            args_client(&args).upload(&args.filepath, remote_path)?;
+            info!("Done with this session");
+        }
+        Action::Run(args) if args.watch => watch::watch(&args, Some(run_options(&args)))?,
+        Action::Run(args) => {
+            let remote_path = remote_path(&args)?;
+            let (_, handle) = args_client(&args).upload_and_run(
+                &args.filepath,
+                remote_path,
+                run_options(&args),
+            )?;
+            let status = handle.wait()?;
+            info!("Done with this session");
+            return Ok(exit_code(status));
+        }
+        Action::Status(args) => {
+            let status = ev3_client(&args.connection, &CompressionArgs::default()).status()?;
+            match args.output {
+                OutputFormat::Human => status::print_status(&status),
+                OutputFormat::Json => print_json(&json!({ "event": "status", "status": status })),
+            }
+        }
+        Action::Delete(args) => {
+            ev3_client(&args.connection, &CompressionArgs::default()).delete(&args.remote_path)?;
+            match args.output {
+                OutputFormat::Human => info!("Deleted {}", args.remote_path.display()),
+                OutputFormat::Json => print_json(&json!({
+                    "event": "deleted",
+                    "remote_path": args.remote_path,
+                })),
+            }
+        }
+        Action::History(args) => {
+            let history = ev3_client(&args.connection, &CompressionArgs::default())
+                .history(&args.remote_path)?;
+            match args.output {
+                OutputFormat::Human => history::print_history(&args.remote_path, &history),
+                OutputFormat::Json => print_json(&json!({
+                    "event": "history",
+                    "remote_path": args.remote_path,
+                    "history": history,
+                })),
+            }
+        }
+        Action::Rollback(args) => {
+            let version = ev3_client(&args.connection, &CompressionArgs::default())
+                .rollback(&args.remote_path, args.to.as_deref())?;
+            match args.output {
+                OutputFormat::Human => info!(
+                    "Rolled {} back to {}",
+                    args.remote_path.display(),
+                    version.hash
+                ),
+                OutputFormat::Json => print_json(&json!({
+                    "event": "rolled_back",
+                    "remote_path": args.remote_path,
+                    "version": version,
+                })),
+            }
+        }
+        Action::Gc(args) => {
+            let report =
+                ev3_client(&args.connection, &CompressionArgs::default()).gc(args.unused_days)?;
+            match args.output {
+                OutputFormat::Human => gc::print_report(&report),
+                OutputFormat::Json => print_json(&json!({ "event": "gc", "report": report })),
+            }
+        }
+        Action::UpdateServer(args) => {
+            let builder = ev3_client_builder(&args.connection, &CompressionArgs::default());
+            let version = output_client(builder, args.output).update_server(&args.binary)?;
+            match args.output {
+                OutputFormat::Human => info!("The server runs ev3-runner {version} now"),
+                OutputFormat::Json => print_json(&json!({
+                    "event": "server_updated",
+                    "version": version,
+                })),
+            }
+        }
+        Action::Discover(args) => {
+            let servers = discover(args.discovery_port, Duration::from_millis(args.timeout))?;
+            match args.output {
+                OutputFormat::Human => discovery::print_servers(&servers),
+                OutputFormat::Json => {
+                    for server in servers {
+                        print_json(&json!({
+                            "event": "server",
+                            "name": server.info.name,
+                            "hostname": server.info.hostname,
+                            "address": server.addr,
+                            "version": server.info.version,
+                        }));
+                    }
+                }
+            }
+        }
+        Action::Runner(args) => {
+            let status = runner::runner(args)?;
+            return Ok(exit_code(status));
+        }
+        Action::Test(args) => return remote_test::test(args),
+    }
 
-pub fn client(config: Client) -> Result<(), ClientError> {
-    let (action, args) = match config.action {
-        Action::Upload(args) => (protocol::Action::Upload, args),
-        Action::Run(args) => (protocol::Action::Run(args.brickrun), args),
+    Ok(ExitCode::SUCCESS)
+}
+
+fn ev3_client(connection: &ConnectionArgs, compression: &CompressionArgs) -> Ev3Client {
+    ev3_client_builder(connection, compression).build()
+}
+
+fn ev3_client_builder(
+    connection: &ConnectionArgs,
+    compression: &CompressionArgs,
+) -> Ev3ClientBuilder {
+    let mode = if compression.auto_compression {
+        CompressionMode::Auto
+    } else if compression.compression || compression.compression_level.is_some() {
+        CompressionMode::On
+    } else {
+        CompressionMode::Off
     };
 
-    let mut session = ClientSession::connect(args, action)?;
-    session.dispatch()?;
+    let mut builder = Ev3Client::builder()
+        .host(&connection.host)
+        .discovery_port(connection.discovery_port)
+        .password(&connection.password)
+        .compression_mode(mode)
+        .compression_level(
+            compression
+                .compression_level
+                .unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+        )
+        .compression_threads(compression.compression_threads.unwrap_or(0))
+        .hash_cache(true)
+        .link_speed_cache(true)
+        .connect_timeout(seconds(connection.timeouts.connect_timeout))
+        .handshake_timeout(seconds(connection.timeouts.handshake_timeout))
+        .timeout(seconds(connection.timeouts.idle_timeout))
+        .keepalive(seconds(connection.timeouts.keepalive));
+    if let Some(user) = &connection.user {
+        builder = builder.user(user);
+    }
+
+    match connection.hash {
+        Some(algorithm) => builder.hash_algorithm(algorithm),
+        None => builder,
+    }
+}
+
+/// Client for `upload` and `run`
+fn args_client(args: &ClientArgs) -> Ev3Client {
+    output_client(
+        ev3_client_builder(&args.connection, &args.compression),
+        args.output,
+    )
+}
+
+/// Client printing every event with `--output json` and drawing a progress bar for uploads
+/// if stderr is a terminal
+fn output_client(builder: Ev3ClientBuilder, output: OutputFormat) -> Ev3Client {
+    match output {
+        OutputFormat::Human if io::stderr().is_terminal() => {
+            let progress_bar = ProgressBar::default();
+            builder
+                .on_event(move |event| progress_bar.handle(event))
+                .build()
+        }
+        OutputFormat::Human => builder.build(),
+        OutputFormat::Json => builder.on_event(print_json).build(),
+    }
+}
+
+fn run_options(args: &ClientArgs) -> RunOptions {
+    let options = RunOptions::new()
+        .brickrun(args.brickrun)
+        .args(args.args.iter().cloned());
+
+    // The output is part of the events
+    match args.output {
+        OutputFormat::Human => options,
+        OutputFormat::Json => options.stdout(Output::Null).stderr(Output::Null),
+    }
+}
+
+/// Prints the value as a single line of JSON
+fn print_json<T: Serialize>(value: &T) {
+    let line = match serde_json::to_string(value) {
+        Ok(line) => line,
+        Err(e) => {
+            tracing::warn!("Failed to serialize an event: {e}");
+            return;
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{line}").and_then(|_| stdout.flush()).ok();
+}
+
+/// The remote path given on the command line, or the name of the local file
+fn remote_path(args: &ClientArgs) -> Result<PathBuf, ClientError> {
+    if let Some(remote_path) = &args.remote_path {
+        return Ok(remote_path.clone());
+    }
+
+    args.filepath
+        .file_name()
+        .map(PathBuf::from)
+        .ok_or_else(|| ClientError::PathNotValid(args.filepath.clone()))
+}
 
-    Ok(())
+fn exit_code(status: ExitStatus) -> ExitCode {
+    u8::try_from(status.exit_code())
+        .map(ExitCode::from)
+        .unwrap_or(ExitCode::FAILURE)
 }
@@ -0,0 +1,506 @@
+use crate::{
+    VERSION,
+    client::{
+        clientsession::ClientError,
+        compression,
+        discovery::resolve_host,
+        ev3client::{Ev3Client, Ev3ClientBuilder, Payload, UploadReport, version_check},
+        events::{ClientEvent, Events, Progress},
+        gc::gc_result,
+        history::rollback_result,
+        run_handle::{Output, RunOptions},
+        validation::check_validation,
+        version::check_version_response,
+    },
+    hash::Hasher,
+    protocol::{
+        Action, ActionResult, ExitStatus, FileHistory, FileVersion, GcReport, GcResult,
+        HashAlgorithm, HashAlgorithms, MatchStatus, OutputStream, Request, RobotStatus,
+        RollbackResult, RunEvent, Validation, VersionHeader, VersionResponse,
+    },
+    transport::{AsyncTransport, TransportError},
+};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWriteExt, BufReader as AsyncBufReader},
+    task, time,
+};
+use tracing::{debug, info, warn};
+
+/// Async version of `Ev3Client`, for use inside a tokio runtime.
+///
+/// ```no_run
+/// use ev3_runner::{Ev3Client, RunOptions};
+///
+/// # async fn deploy() -> Result<(), ev3_runner::ClientError> {
+/// let client = Ev3Client::builder()
+///     .host("192.168.1.100:6767")
+///     .password("mysecret")
+///     .build_async();
+///
+/// let mut handle = client.upload_and_run("robot", "robot", RunOptions::new()).await?.1;
+/// let status = handle.wait().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AsyncEv3Client {
+    client: Ev3Client,
+}
+
+impl From<Ev3Client> for AsyncEv3Client {
+    fn from(client: Ev3Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Ev3ClientBuilder {
+    pub fn build_async(self) -> AsyncEv3Client {
+        self.build().into()
+    }
+}
+
+impl AsyncEv3Client {
+    /// Uploads a local file, skipping the transfer if the server already has it
+    pub async fn upload(
+        &self,
+        path: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+    ) -> Result<UploadReport, ClientError> {
+        let (_, report) = self
+            .deploy_file(path.as_ref(), remote.as_ref(), Action::Upload)
+            .await?;
+        Ok(report)
+    }
+
+    /// Uploads the bytes as a file, skipping the transfer if the server already has it
+    pub async fn upload_bytes(
+        &self,
+        bytes: &[u8],
+        remote: impl AsRef<Path>,
+    ) -> Result<UploadReport, ClientError> {
+        let (transport, hash_algorithms) = self.connect().await?;
+        let algorithm = self.client.hash_algorithm(&hash_algorithms)?;
+        let hash = Hasher::digest_bytes(algorithm, bytes);
+        let size = bytes.len() as u64;
+        let compression = self.client.compression_for(|| Ok(bytes.to_vec()))?;
+        let payload = Payload {
+            reader: bytes,
+            hash,
+            size,
+            compression,
+        };
+        let (_, report) = self
+            .deploy(transport, payload, remote.as_ref(), Action::Upload)
+            .await?;
+        Ok(report)
+    }
+
+    /// Runs a file that is already on the server
+    pub async fn run(
+        &self,
+        remote: impl AsRef<Path>,
+        options: RunOptions,
+    ) -> Result<AsyncRunHandle, ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let request = self
+            .client
+            .request(Action::Exec(options.program()), remote.as_ref(), None);
+        self.request(&mut transport, &request).await?;
+
+        Ok(AsyncRunHandle::new(
+            transport,
+            &options,
+            &self.client.events,
+        ))
+    }
+
+    /// Uploads a local file if the server doesn't have it yet and runs it
+    pub async fn upload_and_run(
+        &self,
+        path: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        options: RunOptions,
+    ) -> Result<(UploadReport, AsyncRunHandle), ClientError> {
+        let action = Action::Run(options.program());
+        let (transport, report) = self
+            .deploy_file(path.as_ref(), remote.as_ref(), action)
+            .await?;
+
+        Ok((
+            report,
+            AsyncRunHandle::new(transport, &options, &self.client.events),
+        ))
+    }
+
+    /// Reads the battery voltage and the connected motors and sensors
+    pub async fn status(&self) -> Result<RobotStatus, ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let request = self.client.request(Action::Status, Path::new(""), None);
+        self.request(&mut transport, &request).await?;
+
+        let status = self
+            .timed(transport.read_and_decode::<RobotStatus>())
+            .await?;
+        debug!("Received robot status: {status:?}");
+        Ok(status)
+    }
+
+    /// Removes a file from the server
+    pub async fn delete(&self, remote: impl AsRef<Path>) -> Result<(), ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let request = self.client.request(Action::Delete, remote.as_ref(), None);
+        self.request(&mut transport, &request).await?;
+
+        match self
+            .timed(transport.read_and_decode::<ActionResult>())
+            .await?
+        {
+            ActionResult::Done => Ok(()),
+            ActionResult::Failed(reason) => Err(ClientError::ActionFailed(reason)),
+        }
+    }
+
+    /// Lists the deployed and the kept earlier versions of a file on the server
+    pub async fn history(&self, remote: impl AsRef<Path>) -> Result<FileHistory, ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let request = self.client.request(Action::History, remote.as_ref(), None);
+        self.request(&mut transport, &request).await?;
+
+        let history = self
+            .timed(transport.read_and_decode::<FileHistory>())
+            .await?;
+        debug!("Received file history: {history:?}");
+        Ok(history)
+    }
+
+    /// Deploys a kept earlier version of a file again, the newest one or the one whose hash
+    /// starts with `to`, and returns it
+    pub async fn rollback(
+        &self,
+        remote: impl AsRef<Path>,
+        to: Option<&str>,
+    ) -> Result<FileVersion, ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let action = Action::Rollback(to.map(str::to_owned));
+        let request = self.client.request(action, remote.as_ref(), None);
+        self.request(&mut transport, &request).await?;
+
+        rollback_result(
+            self.timed(transport.read_and_decode::<RollbackResult>())
+                .await?,
+        )
+    }
+
+    /// Removes the deployed files on the server that weren't uploaded, run or skipped for
+    /// `unused_days`, least recently used first
+    pub async fn gc(&self, unused_days: u32) -> Result<GcReport, ClientError> {
+        let (mut transport, _) = self.connect().await?;
+        let request = self
+            .client
+            .request(Action::Gc(unused_days), Path::new(""), None);
+        self.request(&mut transport, &request).await?;
+
+        gc_result(self.timed(transport.read_and_decode::<GcResult>()).await?)
+    }
+
+    async fn deploy_file(
+        &self,
+        path: &Path,
+        remote: &Path,
+        action: Action,
+    ) -> Result<(AsyncTransport, UploadReport), ClientError> {
+        if !path.is_file() {
+            return Err(ClientError::PathNotValid(path.to_owned()));
+        }
+
+        let (transport, hash_algorithms) = self.connect().await?;
+        let algorithm = self.client.hash_algorithm(&hash_algorithms)?;
+
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        let client = self.client.clone();
+        let hash_path = path.to_owned();
+        let (hash, compression) = task::spawn_blocking(move || {
+            let file = File::open(&hash_path)?;
+            let metadata = file.metadata()?;
+            let mut reader = BufReader::new(file);
+            let hash = client.hash_file(&hash_path, &metadata, algorithm, &mut reader)?;
+            let compression = client.compression_for(|| compression::sample(&mut reader, size))?;
+            Ok::<_, io::Error>((hash, compression))
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        let payload = Payload {
+            reader: AsyncBufReader::new(file),
+            hash,
+            size,
+            compression,
+        };
+        self.deploy(transport, payload, remote, action).await
+    }
+
+    /// Sends the request and uploads the file if the server doesn't have it yet
+    async fn deploy<R: AsyncRead + Unpin>(
+        &self,
+        mut transport: AsyncTransport,
+        payload: Payload<R>,
+        remote: &Path,
+        action: Action,
+    ) -> Result<(AsyncTransport, UploadReport), ClientError> {
+        let Payload {
+            mut reader,
+            hash,
+            size,
+            compression,
+        } = payload;
+        let request = Request {
+            size: Some(size),
+            compression,
+            ..self.client.request(action, remote, Some(hash))
+        };
+        let validation = self.request(&mut transport, &request).await?;
+
+        let events = &self.client.events;
+        let skipped = validation.hash == MatchStatus::Match;
+        let bytes = if skipped {
+            info!("Remote file already up to date, no upload needed");
+            events.emit(ClientEvent::UploadSkipped {
+                remote_path: remote.to_owned(),
+            });
+            0
+        } else {
+            info!("Uploading file because remote hash did not match");
+            events.emit(ClientEvent::UploadStarted {
+                remote_path: remote.to_owned(),
+                size,
+            });
+
+            let started = Instant::now();
+            let mut progress = Progress::new(events.clone(), size);
+            let transferred = transport
+                .upload_file(&mut reader, compression, |transferred| {
+                    progress.update(transferred)
+                })
+                .await?;
+            self.client
+                .link_speed
+                .record(transferred.written, transferred.wire_time);
+
+            events.emit(ClientEvent::UploadFinished {
+                remote_path: remote.to_owned(),
+                bytes: transferred.read,
+                wire_bytes: transferred.written,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+            transferred.read
+        };
+
+        let report = UploadReport {
+            remote_path: remote.to_owned(),
+            skipped,
+            bytes,
+        };
+        Ok((transport, report))
+    }
+
+    /// Connects to the server, checks that the versions match and returns the hash
+    /// algorithms the server accepts
+    async fn connect(&self) -> Result<(AsyncTransport, Vec<HashAlgorithm>), ClientError> {
+        let address = self.address().await?;
+        let timeouts = self.client.timeouts;
+        let mut transport = AsyncTransport::connect(&address, timeouts.connect).await?;
+        transport.set_keepalive(timeouts.keepalive)?;
+        debug!("Connected to {address}");
+
+        let deadline = timeouts.handshake_deadline();
+        transport
+            .encode_and_write(VersionHeader(VERSION.to_owned()))
+            .await?;
+        let response = self
+            .handshake(deadline, transport.read_and_decode::<VersionResponse>())
+            .await?;
+        let checked = check_version_response(response);
+        match &checked {
+            Ok(_) => self.client.events.emit(version_check(None)),
+            Err(ClientError::VersionMismatch(server_version)) => self
+                .client
+                .events
+                .emit(version_check(Some(server_version.clone()))),
+            Err(_) => {}
+        }
+        checked?;
+
+        let HashAlgorithms(hash_algorithms) = self
+            .handshake(deadline, transport.read_and_decode())
+            .await?;
+        debug!("Server accepts {hash_algorithms:?} hashes");
+        Ok((transport, hash_algorithms))
+    }
+
+    async fn request(
+        &self,
+        transport: &mut AsyncTransport,
+        request: &Request,
+    ) -> Result<Validation, ClientError> {
+        // Hashing the file may have taken a while, the server only has to answer quickly
+        let deadline = self.client.timeouts.handshake_deadline();
+        transport.encode_and_write(request).await?;
+
+        let validation = self
+            .handshake(deadline, transport.read_and_decode::<Validation>())
+            .await?;
+        self.client.events.emit(validation.into());
+        check_validation(validation, request.action.uses_file())
+    }
+
+    /// Address of the server, looking up the robot name on first use
+    async fn address(&self) -> Result<String, ClientError> {
+        if let Some(address) = self.client.address.get() {
+            return Ok(address.clone());
+        }
+
+        let host = self.client.host.clone();
+        let discovery_port = self.client.discovery_port;
+        let address = task::spawn_blocking(move || resolve_host(&host, discovery_port))
+            .await
+            .map_err(io::Error::other)??;
+
+        Ok(self.client.address.get_or_init(|| address).clone())
+    }
+
+    /// Waits for an answer during the handshake until the deadline, or like `timed` if there
+    /// is no handshake timeout
+    async fn handshake<T>(
+        &self,
+        deadline: Option<Instant>,
+        response: impl Future<Output = Result<T, TransportError>>,
+    ) -> Result<T, ClientError> {
+        match deadline {
+            Some(deadline) => time::timeout_at(deadline.into(), response)
+                .await
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Server didn't finish the handshake in time",
+                    )
+                })?
+                .map_err(Into::into),
+            None => self.timed(response).await,
+        }
+    }
+
+    /// Waits for a response of the server for at most the configured timeout
+    async fn timed<T>(
+        &self,
+        response: impl Future<Output = Result<T, TransportError>>,
+    ) -> Result<T, ClientError> {
+        match self.client.timeouts.idle {
+            Some(timeout) => time::timeout(timeout, response)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Server didn't respond"))?
+                .map_err(Into::into),
+            None => response.await.map_err(Into::into),
+        }
+    }
+}
+
+/// A program running on the server, driven by the async client.
+///
+/// Output is only received while `next_output` or `wait` is being awaited.
+/// Dropping the handle closes the connection, which stops the program.
+pub struct AsyncRunHandle {
+    transport: AsyncTransport,
+    stdout: Output,
+    stderr: Output,
+    events: Events,
+    status: Option<ExitStatus>,
+}
+
+impl AsyncRunHandle {
+    fn new(transport: AsyncTransport, options: &RunOptions, events: &Events) -> Self {
+        Self {
+            transport,
+            stdout: options.stdout,
+            stderr: options.stderr,
+            events: events.clone(),
+            status: None,
+        }
+    }
+
+    /// Receives the next chunk of piped output, `None` once the program exited.
+    ///
+    /// Output that isn't piped is written or discarded on the way.
+    pub async fn next_output(&mut self) -> Result<Option<(OutputStream, Vec<u8>)>, ClientError> {
+        while self.status.is_none() {
+            let (stream, chunk) = match self.transport.read_and_decode::<RunEvent>().await? {
+                RunEvent::Output(stream, chunk) => (stream, chunk),
+                RunEvent::Exit(status) => {
+                    if status.success() {
+                        info!("Program exited with {status}");
+                    } else {
+                        warn!("Program exited with {status}");
+                    }
+                    self.events.emit(status.into());
+                    self.status = Some(status);
+                    break;
+                }
+            };
+            self.events.output(stream, &chunk);
+
+            let output = match stream {
+                OutputStream::Stdout => self.stdout,
+                OutputStream::Stderr => self.stderr,
+            };
+            match output {
+                Output::Piped => return Ok(Some((stream, chunk))),
+                Output::Inherit => write_inherited(stream, &chunk).await?,
+                Output::Null => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Waits for the program to exit, discarding piped output that wasn't read yet
+    pub async fn wait(&mut self) -> Result<ExitStatus, ClientError> {
+        while self.next_output().await?.is_some() {}
+
+        self.status
+            .ok_or_else(|| io::Error::other("Program exited without exit status").into())
+    }
+
+    /// Stops the program by closing the connection
+    pub async fn kill(mut self) -> Result<(), ClientError> {
+        self.transport.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+async fn write_inherited(stream: OutputStream, chunk: &[u8]) -> Result<(), io::Error> {
+    match stream {
+        OutputStream::Stdout => write_flush(&mut tokio::io::stdout(), chunk).await,
+        OutputStream::Stderr => write_flush(&mut tokio::io::stderr(), chunk).await,
+    }
+}
+
+async fn write_flush<W: tokio::io::AsyncWrite + Unpin>(
+    output: &mut W,
+    chunk: &[u8],
+) -> Result<(), io::Error> {
+    output
+        .write_all(chunk)
+        .await
+        .inspect_err(|e| warn!("Failed to write to the output: {e}"))?;
+    output
+        .flush()
+        .await
+        .inspect_err(|e| warn!("Failed to flush the output: {e}"))
+}
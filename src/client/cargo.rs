@@ -0,0 +1,190 @@
+use crate::{
+    cli::{BuildArgs, CargoAction, CargoEv3},
+    client::{clientsession::ClientError, ev3_client, exit_code, run_handle::RunOptions},
+    config::Config,
+};
+use serde::Deserialize;
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    process::{Command, ExitCode, Stdio},
+};
+use tracing::{debug, info};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CargoError {
+    #[error("Failed to run cargo: {0}")]
+    Io(#[from] io::Error),
+    #[error("cargo build failed with {0}")]
+    BuildFailed(std::process::ExitStatus),
+    #[error("Failed to parse cargo output: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("cargo build didn't produce a binary")]
+    NoBinary,
+    #[error("Multiple binaries were built, select one with --bin or --example: {0}")]
+    MultipleBinaries(String),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+}
+
+/// Message printed by `cargo build --message-format json`
+#[derive(Debug, Deserialize)]
+struct CargoMessage {
+    reason: String,
+    target: Option<CargoTarget>,
+    executable: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Artifact {
+    name: String,
+    kind: String,
+    executable: PathBuf,
+}
+
+/// Builds the project for the EV3 and uploads or runs the produced binary
+pub fn cargo_ev3(config: CargoEv3) -> Result<ExitCode, CargoError> {
+    let (args, run) = match config.action {
+        CargoAction::Upload(args) => (args, false),
+        CargoAction::Run(args) => (args, true),
+    };
+
+    let artifact = select_artifact(&args.build, build(&args.build)?)?;
+    info!("Built {} {}", artifact.kind, artifact.name);
+
+    let config = Config::load().map_err(ClientError::from)?;
+    let connection = config.connection(args.connection);
+    let client = ev3_client(&connection, &config.compression(args.compression));
+
+    let remote_path = match args.remote_path {
+        Some(remote_path) => remote_path,
+        None => PathBuf::from(&artifact.name),
+    };
+
+    if !run {
+        client.upload(&artifact.executable, remote_path)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let options = RunOptions::new()
+        .brickrun(args.brickrun || config.brickrun)
+        .args(args.args);
+    let (_, handle) = client.upload_and_run(&artifact.executable, remote_path, options)?;
+
+    Ok(exit_code(handle.wait()?))
+}
+
+/// Runs `cargo build` and returns all binaries and examples it produced
+fn build(args: &BuildArgs) -> Result<Vec<Artifact>, CargoError> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let mut command = Command::new(cargo);
+    command
+        .arg("build")
+        .arg("--message-format=json-render-diagnostics")
+        .args(["--target", &args.target]);
+
+    if let Some(bin) = &args.bin {
+        command.args(["--bin", bin]);
+    }
+    if let Some(example) = &args.example {
+        command.args(["--example", example]);
+    }
+    if let Some(package) = &args.package {
+        command.args(["--package", package]);
+    }
+    if args.release {
+        command.arg("--release");
+    }
+    if let Some(profile) = &args.profile {
+        command.args(["--profile", profile]);
+    }
+    for features in &args.features {
+        command.args(["--features", features]);
+    }
+    if args.all_features {
+        command.arg("--all-features");
+    }
+    if args.no_default_features {
+        command.arg("--no-default-features");
+    }
+    if let Some(manifest_path) = &args.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+
+    debug!("Running {command:?}");
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("stdout of cargo is not piped"))?;
+
+    let mut artifacts = Vec::new();
+    for line in BufReader::new(stdout).lines() {
+        let message: CargoMessage = serde_json::from_str(&line?)?;
+        if message.reason != "compiler-artifact" {
+            continue;
+        }
+
+        let (Some(target), Some(executable)) = (message.target, message.executable) else {
+            continue;
+        };
+        let Some(kind) = target
+            .kind
+            .into_iter()
+            .find(|kind| kind == "bin" || kind == "example")
+        else {
+            continue;
+        };
+
+        debug!("Found {kind} {} at {}", target.name, executable.display());
+        artifacts.push(Artifact {
+            name: target.name,
+            kind,
+            executable,
+        });
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(CargoError::BuildFailed(status));
+    }
+
+    Ok(artifacts)
+}
+
+fn select_artifact(args: &BuildArgs, artifacts: Vec<Artifact>) -> Result<Artifact, CargoError> {
+    let selected = match (&args.bin, &args.example) {
+        (Some(name), _) => ("bin", name),
+        (_, Some(name)) => ("example", name),
+        (None, None) => {
+            let mut binaries: Vec<Artifact> = artifacts
+                .into_iter()
+                .filter(|artifact| artifact.kind == "bin")
+                .collect();
+            return match binaries.len() {
+                0 => Err(CargoError::NoBinary),
+                1 => Ok(binaries.remove(0)),
+                _ => Err(CargoError::MultipleBinaries(
+                    binaries
+                        .iter()
+                        .map(|artifact| artifact.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )),
+            };
+        }
+    };
+
+    artifacts
+        .into_iter()
+        .find(|artifact| artifact.kind == selected.0 && artifact.name == *selected.1)
+        .ok_or(CargoError::NoBinary)
+}
@@ -1,17 +1,16 @@
 use crate::{
-    cli::ClientArgs,
-    hash::Hasher,
-    protocol::{Action, PathStatus, Request},
+    client::{events::Events, validation::check_validation},
+    config::ConfigError,
+    protocol::{ActionResult, HashAlgorithm, PathStatus, Request, SpaceStatus, Validation},
     transport::{Transport, TransportError},
 };
 use bincode::error::{DecodeError, EncodeError};
 use std::{
-    fs::File,
-    io::{self, BufReader, Seek},
-    net::Shutdown,
+    io,
     path::PathBuf,
+    time::{Duration, Instant},
 };
-use tracing::{error, info};
+use tracing::debug;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
@@ -19,10 +18,32 @@ pub enum ClientError {
     PathNotValid(PathBuf),
     #[error("Remote path is not valid: {0}")]
     RemotePath(#[from] PathStatus),
+    #[error("The server refused the upload: {0}")]
+    UploadRefused(SpaceStatus),
     #[error("Passwords not valid")]
     PasswordNotValid,
+    #[error("Too many wrong passwords, try again in {}s", .0.as_secs())]
+    LockedOut(Duration),
+    #[error("The account isn't allowed to do this")]
+    Forbidden,
+    #[error("The server failed to do this: {0}")]
+    ActionFailed(String),
+    #[error("No robot named {0:?} answered the discovery request")]
+    RobotNotFound(String),
     #[error("Version mismatch: {0}")]
     VersionMismatch(String),
+    #[error("The server ({0}) is too old to be updated remotely")]
+    UpdateNotSupported(String),
+    #[error("The update failed: {0}")]
+    UpdateFailed(String),
+    #[error("The new binary failed to start, the server went back to {0}")]
+    UpdateRolledBack(String),
+    #[error("The server doesn't accept {0} hashes")]
+    HashAlgorithmNotAccepted(HashAlgorithm),
+    #[error("The program was stopped before it exited")]
+    Stopped,
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
     #[error("Error in transport layer: {0}")]
     Transport(#[from] TransportError),
     #[error("Io error: {0}")]
@@ -33,74 +54,81 @@ pub enum ClientError {
     Decode(#[from] DecodeError),
 }
 
+/// Timeouts of a connection, `None` waits forever
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Timeouts {
+    pub(super) connect: Option<Duration>,
+    /// For the answer to the version check and to the request, each
+    pub(super) handshake: Option<Duration>,
+    /// For every read and write after the handshake, and during it if there is no handshake
+    /// timeout
+    pub(super) idle: Option<Duration>,
+    pub(super) keepalive: Option<Duration>,
+}
+
+impl Timeouts {
+    /// When the answer to a message sent now has to have arrived
+    pub(super) fn handshake_deadline(&self) -> Option<Instant> {
+        self.handshake.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+/// A single connection to the server, which handles exactly one request
 pub struct ClientSession {
     pub(super) transport: Transport,
-    pub(super) args: ClientArgs,
-    pub(super) action: Action,
+    /// Hash algorithms the server accepts, most preferred first
+    pub(super) hash_algorithms: Vec<HashAlgorithm>,
+    timeouts: Timeouts,
 }
 
 impl ClientSession {
-    pub fn connect(args: ClientArgs, action: Action) -> Result<Self, ClientError> {
-        let transport = Transport::connect(&args.host)?;
-        Ok(Self {
-            transport,
-            args,
-            action,
-        })
+    /// Connects to the server and checks that the versions match
+    pub(super) fn connect(addr: &str, timeouts: Timeouts) -> Result<Self, ClientError> {
+        let mut session = Self {
+            transport: Self::open(addr, timeouts)?,
+            hash_algorithms: Vec::new(),
+            timeouts,
+        };
+        session.check_version()?;
+        Ok(session)
     }
 
-    pub fn dispatch(&mut self) -> Result<(), ClientError> {
-        self.check_version()?;
-
-        let (req, reader) = self.setup()?;
-        self.transport.encode_and_write(&req)?;
-
-        self.validation(reader)?;
-
-        if self.action == Action::Upload {
-            info!("Done with this session");
-            return Ok(());
-        }
-
-        let mut stdout = io::stdout();
-        self.transport.receive_output(&mut stdout)?;
-
-        self.transport.stream.shutdown(Shutdown::Both)?;
-        info!("Done with this session");
+    /// Connects to the server with the handshake deadline set
+    pub(super) fn open(addr: &str, timeouts: Timeouts) -> Result<Transport, ClientError> {
+        let mut transport = Transport::connect(addr, timeouts.connect)?;
+        transport.set_keepalive(timeouts.keepalive)?;
+        transport.set_read_timeout(timeouts.idle)?;
+        transport.set_write_timeout(timeouts.handshake.or(timeouts.idle))?;
+        transport.set_deadline(timeouts.handshake_deadline());
+        debug!("Connected to {addr}");
+        Ok(transport)
+    }
 
-        Ok(())
+    /// Sends the request and returns the validated response of the server
+    pub fn request(
+        &mut self,
+        request: &Request,
+        events: &Events,
+    ) -> Result<Validation, ClientError> {
+        // Hashing the file may have taken a while, the server only has to answer quickly
+        self.transport
+            .set_deadline(self.timeouts.handshake_deadline());
+        self.transport.encode_and_write(request)?;
+
+        let validation = self.transport.read_and_decode::<Validation>()?;
+        self.transport.set_deadline(None);
+        self.transport.set_read_timeout(self.timeouts.idle)?;
+        self.transport.set_write_timeout(self.timeouts.idle)?;
+
+        events.emit(validation.into());
+        check_validation(validation, request.action.uses_file())
     }
 
-    fn setup(&mut self) -> Result<(Request, BufReader<File>), ClientError> {
-        if !self.args.filepath.is_file() {
-            return Err(ClientError::PathNotValid(self.args.filepath.clone()));
+    /// Reads the answer to an action that doesn't upload or run anything
+    pub(super) fn receive_result(&mut self) -> Result<(), ClientError> {
+        match self.transport.read_and_decode::<ActionResult>()? {
+            ActionResult::Done => Ok(()),
+            ActionResult::Failed(reason) => Err(ClientError::ActionFailed(reason)),
         }
-
-        let remote_path = self.args.remote_path.clone().map(Ok).unwrap_or_else(
-            || -> Result<PathBuf, ClientError> {
-                self.args
-                    .filepath
-                    .file_name()
-                    .map(PathBuf::from)
-                    .ok_or_else(|| ClientError::PathNotValid(self.args.filepath.clone()))
-            },
-        )?;
-
-        let file = File::open(&self.args.filepath)?;
-        let mut reader = BufReader::new(file);
-
-        let hash = Hasher::hash_file(&mut reader)?;
-        reader.rewind()?;
-
-        let password_hash = Hasher::hash_password(&self.args.password);
-        let request = Request {
-            action: self.action,
-            path: remote_path,
-            hash,
-            use_compression: self.args.compression,
-            password: password_hash,
-        };
-
-        Ok((request, reader))
     }
 }
@@ -0,0 +1,274 @@
+use crate::{
+    client::hash_cache::{save_json, user_cache_dir},
+    protocol::Compression,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// When uploads are compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionMode {
+    #[default]
+    Off,
+    On,
+    /// Compress if a sample of the file compresses well and fast enough for the link speed
+    Auto,
+}
+
+/// Samples taken from the file in auto mode, spread over the whole file
+const SAMPLE_COUNT: u64 = 4;
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Compression has to make the upload at least this much faster to be used
+const MIN_SPEEDUP: f64 = 1.1;
+
+/// Link speed assumed until an upload was measured, roughly what an EV3 manages over USB or
+/// a Wi-Fi dongle
+const DEFAULT_LINK_SPEED: u64 = 1024 * 1024;
+
+/// Uploads shorter than this are too noisy to measure the link speed
+const MIN_MEASURE_DURATION: Duration = Duration::from_millis(200);
+
+/// Bytes per second on the wire measured during the last upload.
+///
+/// With a file, the speed is loaded from it on creation and saved to it after every
+/// measurement, per host, so new clients start from the last measured speed.
+#[derive(Debug, Default)]
+pub(super) struct LinkSpeed {
+    speed: AtomicU64,
+    saved: Option<SavedSpeed>,
+}
+
+#[derive(Debug)]
+struct SavedSpeed {
+    file: PathBuf,
+    host: String,
+}
+
+impl LinkSpeed {
+    /// Speed of `host` saved in the user's cache directory, the default if there is none
+    pub(super) fn user(host: &str) -> Self {
+        match user_cache_dir() {
+            Some(dir) => Self::load(dir.join("ev3-runner").join("link_speeds.json"), host),
+            None => Self::default(),
+        }
+    }
+
+    fn load(file: PathBuf, host: &str) -> Self {
+        let speed = read_speeds(&file).get(host).copied().unwrap_or(0);
+        Self {
+            speed: AtomicU64::new(speed),
+            saved: Some(SavedSpeed {
+                file,
+                host: host.to_owned(),
+            }),
+        }
+    }
+
+    pub(super) fn get(&self) -> f64 {
+        match self.speed.load(Ordering::Relaxed) {
+            0 => DEFAULT_LINK_SPEED as f64,
+            speed => speed as f64,
+        }
+    }
+
+    /// Records an upload of `wire_bytes` that spent `wire_time` writing to the connection
+    pub(super) fn record(&self, wire_bytes: u64, wire_time: Duration) {
+        if wire_time < MIN_MEASURE_DURATION {
+            return;
+        }
+
+        let speed = ((wire_bytes as f64 / wire_time.as_secs_f64()) as u64).max(1);
+        debug!("Measured link speed: {speed} bytes/s");
+        self.speed.store(speed, Ordering::Relaxed);
+
+        if let Some(saved) = &self.saved {
+            let mut speeds = read_speeds(&saved.file);
+            speeds.insert(saved.host.clone(), speed);
+            if let Err(e) = save_json(&saved.file, &speeds) {
+                warn!(
+                    "Failed to save the link speed {}: {e}",
+                    saved.file.display()
+                );
+            }
+        }
+    }
+}
+
+/// Bytes per second by host, empty if the file is missing or broken
+fn read_speeds(file: &Path) -> HashMap<String, u64> {
+    match fs::read(file) {
+        Ok(content) => serde_json::from_slice(&content)
+            .inspect_err(|e| warn!("Ignoring broken link speeds {}: {e}", file.display()))
+            .unwrap_or_default(),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to read link speeds {}: {e}", file.display());
+            }
+            HashMap::new()
+        }
+    }
+}
+
+/// How well and how fast a sample of the file compressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Estimate {
+    /// Sample size divided by the compressed size
+    pub(super) ratio: f64,
+    /// Bytes of the file compressed per second
+    pub(super) speed: f64,
+}
+
+/// Reads `SAMPLE_COUNT` evenly spaced chunks of the file, or all of a small file, and
+/// rewinds it
+pub(super) fn sample<R: Read + Seek>(file: &mut R, size: u64) -> Result<Vec<u8>, io::Error> {
+    let mut sample = Vec::new();
+    if size <= SAMPLE_COUNT * SAMPLE_SIZE as u64 {
+        file.read_to_end(&mut sample)?;
+    } else {
+        for i in 0..SAMPLE_COUNT {
+            file.seek(SeekFrom::Start(i * (size / SAMPLE_COUNT)))?;
+            file.by_ref()
+                .take(SAMPLE_SIZE as u64)
+                .read_to_end(&mut sample)?;
+        }
+    }
+    file.rewind()?;
+
+    Ok(sample)
+}
+
+pub(super) fn estimate(sample: &[u8], level: i32, workers: u32) -> Result<Estimate, io::Error> {
+    let started = Instant::now();
+    let compressed = zstd::bulk::compress(sample, level)?;
+    let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    Ok(Estimate {
+        ratio: sample.len() as f64 / compressed.len().max(1) as f64,
+        // Assumes the workers scale about linearly, the sample is too small to split up
+        speed: sample.len() as f64 / elapsed * workers.max(1) as f64,
+    })
+}
+
+/// Whether compressing makes the upload faster.
+///
+/// Compressing and sending overlap, so the compressed upload takes as long as the slower of
+/// the two.
+pub(super) fn pays_off(estimate: Estimate, link_speed: f64) -> bool {
+    let raw = 1.0 / link_speed;
+    let compressed = (1.0 / estimate.speed).max(1.0 / (estimate.ratio * link_speed));
+    raw / compressed >= MIN_SPEEDUP
+}
+
+/// Settings for one upload, `sample` is only called in auto mode
+pub(super) fn choose(
+    mode: CompressionMode,
+    level: i32,
+    workers: u32,
+    link_speed: &LinkSpeed,
+    sample: impl FnOnce() -> Result<Vec<u8>, io::Error>,
+) -> Result<Compression, io::Error> {
+    let zstd = Compression::Zstd { level, workers };
+    match mode {
+        CompressionMode::Off => Ok(Compression::None),
+        CompressionMode::On => Ok(zstd),
+        CompressionMode::Auto => {
+            let sample = sample()?;
+            if sample.is_empty() {
+                return Ok(Compression::None);
+            }
+
+            let estimate = estimate(&sample, level, workers)?;
+            let link_speed = link_speed.get();
+            let compress = pays_off(estimate, link_speed);
+            info!(
+                "Sample compresses {:.2}x at {:.1} MiB/s, link speed {:.1} MiB/s: {}",
+                estimate.ratio,
+                estimate.speed / (1024.0 * 1024.0),
+                link_speed / (1024.0 * 1024.0),
+                if compress {
+                    "compressing"
+                } else {
+                    "not compressing"
+                }
+            );
+
+            Ok(if compress { zstd } else { Compression::None })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Cursor;
+
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    #[test]
+    fn test_sample_spreads_over_file() {
+        let file: Vec<u8> = (0..1024 * 1024u32).map(|i| (i / 4096) as u8).collect();
+        let mut cursor = Cursor::new(&file);
+        let sample = sample(&mut cursor, file.len() as u64).unwrap();
+
+        assert_eq!(sample.len(), SAMPLE_COUNT as usize * SAMPLE_SIZE);
+        assert_eq!(sample[SAMPLE_SIZE], file[file.len() / 4]);
+        assert_eq!(cursor.position(), 0);
+    }
+
+    #[test]
+    fn test_sample_small_file() {
+        let file = vec![7u8; 1000];
+        let sample = sample(&mut Cursor::new(&file), file.len() as u64).unwrap();
+        assert_eq!(sample, file);
+    }
+
+    #[test]
+    fn test_pays_off() {
+        let compressible = Estimate {
+            ratio: 3.0,
+            speed: 50.0 * MIB,
+        };
+        let incompressible = Estimate {
+            ratio: 1.01,
+            speed: 50.0 * MIB,
+        };
+
+        assert!(pays_off(compressible, MIB));
+        assert!(!pays_off(incompressible, MIB));
+        // Compressing is slower than sending on a fast link
+        assert!(!pays_off(compressible, 100.0 * MIB));
+    }
+
+    #[test]
+    fn test_link_speed_ignores_short_uploads() {
+        let link_speed = LinkSpeed::default();
+        link_speed.record(100, Duration::from_millis(1));
+        assert_eq!(link_speed.get(), DEFAULT_LINK_SPEED as f64);
+
+        link_speed.record(4 * 1024 * 1024, Duration::from_secs(2));
+        assert_eq!(link_speed.get(), 2.0 * MIB);
+    }
+
+    #[test]
+    fn test_link_speed_is_saved_per_host() {
+        let tmp = TempDir::new("link-speed");
+        let dir = tmp.path();
+        let file = dir.join("link_speeds.json");
+
+        LinkSpeed::load(file.clone(), "ev3:6767").record(6 * 1024 * 1024, Duration::from_secs(2));
+        assert_eq!(LinkSpeed::load(file.clone(), "ev3:6767").get(), 3.0 * MIB);
+        assert_eq!(
+            LinkSpeed::load(file, "other:6767").get(),
+            DEFAULT_LINK_SPEED as f64
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    client::clientsession::ClientError,
+    protocol::{DISCOVERY_MAGIC, DiscoveryResponse},
+};
+use bincode::config::standard;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    /// Address to connect to over TCP
+    pub addr: SocketAddr,
+    pub info: DiscoveryResponse,
+}
+
+/// Broadcasts a discovery request and collects all answers until the timeout expires
+pub fn discover(
+    discovery_port: u16,
+    timeout: Duration,
+) -> Result<Vec<DiscoveredServer>, io::Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(DISCOVERY_MAGIC, (Ipv4Addr::BROADCAST, discovery_port))?;
+    debug!("Sent discovery broadcast to port {discovery_port}");
+
+    let deadline = Instant::now() + timeout;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (n, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let info: DiscoveryResponse = match bincode::decode_from_slice(&buf[..n], standard()) {
+            Ok((info, _)) => info,
+            Err(e) => {
+                warn!("Ignoring invalid discovery answer from {peer}: {e}");
+                continue;
+            }
+        };
+
+        let addr = SocketAddr::new(peer.ip(), info.port);
+        if servers.iter().any(|server| server.addr == addr) {
+            continue;
+        }
+        info!("Found server {} at {addr}", info.name);
+        servers.push(DiscoveredServer { addr, info });
+    }
+
+    servers.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+    Ok(servers)
+}
+
+/// Turns the value of `--host` into an address.
+///
+/// Values containing a `:` are used as they are, everything else is treated as
+/// a robot name and looked up on the local network.
+pub fn resolve_host(host: &str, discovery_port: u16) -> Result<String, ClientError> {
+    if host.contains(':') {
+        return Ok(host.to_owned());
+    }
+
+    let servers = discover(discovery_port, RESOLVE_TIMEOUT)?;
+    servers
+        .into_iter()
+        .find(|server| {
+            server.info.name.eq_ignore_ascii_case(host)
+                || server.info.hostname.eq_ignore_ascii_case(host)
+        })
+        .map(|server| server.addr.to_string())
+        .ok_or_else(|| ClientError::RobotNotFound(host.to_owned()))
+}
+
+const RESOLVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub fn print_servers(servers: &[DiscoveredServer]) {
+    if servers.is_empty() {
+        println!("No servers found");
+        return;
+    }
+
+    for server in servers {
+        println!(
+            "{:<16} {:<22} hostname: {}, version: {}",
+            server.info.name,
+            server.addr.to_string(),
+            server.info.hostname,
+            server.info.version
+        );
+    }
+}
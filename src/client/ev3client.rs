@@ -0,0 +1,567 @@
+use crate::{
+    VERSION,
+    cli::{
+        DEFAULT_COMPRESSION_LEVEL, DEFAULT_DISCOVERY_PORT, DEFAULT_HOST, DEFAULT_PASSWORD,
+        MAX_COMPRESSION_LEVEL, MIN_COMPRESSION_LEVEL,
+    },
+    client::{
+        clientsession::{ClientError, ClientSession, Timeouts},
+        compression::{self, CompressionMode, LinkSpeed},
+        discovery::resolve_host,
+        events::{ClientEvent, Events, Progress},
+        hash_cache::HashCache,
+        run_handle::{RunHandle, RunOptions},
+    },
+    hash::Hasher,
+    protocol::{
+        Action, Compression, Digest, FileHistory, FileVersion, GcReport, HashAlgorithm,
+        MatchStatus, Request, RobotStatus,
+    },
+};
+use std::{
+    fs::{File, Metadata},
+    io::{self, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+/// Client to upload and run programs on an ev3-runner server.
+///
+/// Every method opens its own connection to the server.
+///
+/// ```no_run
+/// use ev3_runner::{Ev3Client, RunOptions};
+///
+/// let client = Ev3Client::builder()
+///     .host("192.168.1.100:6767")
+///     .password("mysecret")
+///     .build();
+///
+/// client.upload("target/armv5te-unknown-linux-musleabi/release/robot", "robot")?;
+/// let status = client.run("robot", RunOptions::new().arg("--fast"))?.wait()?;
+/// println!("robot exited with {status}");
+/// # Ok::<(), ev3_runner::ClientError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Ev3Client {
+    pub(super) host: String,
+    pub(super) discovery_port: u16,
+    pub(super) user: Option<String>,
+    pub(super) password: [u8; 32],
+    pub(super) compression: CompressionMode,
+    pub(super) compression_level: i32,
+    pub(super) compression_threads: u32,
+    /// Shared by clones, so later uploads use the speed measured by earlier ones
+    pub(super) link_speed: Arc<LinkSpeed>,
+    pub(super) hash_cache: Option<Arc<HashCache>>,
+    pub(super) hash_algorithm: Option<HashAlgorithm>,
+    pub(super) timeouts: Timeouts,
+    /// Address the host resolved to, looked up on first use
+    pub(super) address: OnceLock<String>,
+    pub(super) events: Events,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ev3ClientBuilder {
+    host: String,
+    discovery_port: u16,
+    user: Option<String>,
+    password: String,
+    compression: CompressionMode,
+    compression_level: i32,
+    compression_threads: u32,
+    hash_cache: bool,
+    link_speed_cache: bool,
+    hash_algorithm: Option<HashAlgorithm>,
+    timeouts: Timeouts,
+    events: Events,
+}
+
+/// A file to deploy together with what the request needs to know about it
+pub(super) struct Payload<R> {
+    pub(super) reader: R,
+    pub(super) hash: Digest,
+    pub(super) size: u64,
+    pub(super) compression: Compression,
+}
+
+/// Result of an upload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadReport {
+    pub remote_path: PathBuf,
+    /// The server already had the file, so nothing was sent
+    pub skipped: bool,
+    /// Bytes of the file that were sent, zero if the upload was skipped
+    pub bytes: u64,
+}
+
+impl Default for Ev3ClientBuilder {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_HOST.to_owned(),
+            discovery_port: DEFAULT_DISCOVERY_PORT,
+            user: None,
+            password: DEFAULT_PASSWORD.to_owned(),
+            compression: CompressionMode::Off,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            compression_threads: 0,
+            hash_cache: false,
+            link_speed_cache: false,
+            hash_algorithm: None,
+            timeouts: Timeouts::default(),
+            events: Events::default(),
+        }
+    }
+}
+
+impl Ev3ClientBuilder {
+    /// Server address as `HOST:PORT`, or the name of a robot to look up on the local network
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// UDP port used to look up robot names
+    pub fn discovery_port(mut self, port: u16) -> Self {
+        self.discovery_port = port;
+        self
+    }
+
+    /// Account to log in with, needed if the server has accounts. The password is the one of
+    /// the account then.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = password.into();
+        self
+    }
+
+    /// Compress uploads with zstd, same as `compression_mode(CompressionMode::On)`
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.compression = if compression {
+            CompressionMode::On
+        } else {
+            CompressionMode::Off
+        };
+        self
+    }
+
+    pub fn compression_mode(mut self, mode: CompressionMode) -> Self {
+        self.compression = mode;
+        self
+    }
+
+    /// zstd level to compress with, higher levels compress better but slower. Levels outside
+    /// of -7 to 19 are clamped, the server can't decode the windows of higher ones.
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.compression_level = level.clamp(MIN_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL);
+        self
+    }
+
+    /// Number of threads compressing in the background, 0 compresses on the calling thread
+    pub fn compression_threads(mut self, threads: u32) -> Self {
+        self.compression_threads = threads;
+        self
+    }
+
+    /// Remember the hashes of uploaded files in the user's cache directory, so unchanged
+    /// files don't have to be read to find out the server already has them
+    pub fn hash_cache(mut self, enabled: bool) -> Self {
+        self.hash_cache = enabled;
+        self
+    }
+
+    /// Remember the link speed measured for the host in the user's cache directory, so
+    /// automatic compression doesn't start from a guess in every new client
+    pub fn link_speed_cache(mut self, enabled: bool) -> Self {
+        self.link_speed_cache = enabled;
+        self
+    }
+
+    /// Hash algorithm used to find out whether the server already has a file.
+    ///
+    /// By default the one the server prefers is used. Fails if the server doesn't accept it.
+    pub fn hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = Some(algorithm);
+        self
+    }
+
+    /// Maximum time to wait for the server to accept the connection
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.connect = timeout.into();
+        self
+    }
+
+    /// Maximum time the server may take to answer the version check and the request, no
+    /// matter how slowly it trickles in the answer
+    pub fn handshake_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.handshake = timeout.into();
+        self
+    }
+
+    /// Maximum time a read or write may block while talking to the server.
+    ///
+    /// Also applies to the handshake if there is no handshake timeout. Doesn't apply while
+    /// waiting for output of a running program.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.idle = timeout.into();
+        self
+    }
+
+    /// Idle time after which TCP keepalive probes check that the server is still there
+    pub fn keepalive(mut self, idle: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.keepalive = idle.into();
+        self
+    }
+
+    /// Called for every `ClientEvent`, e.g. to report the progress of an upload
+    pub fn on_event(mut self, handler: impl Fn(&ClientEvent) + Send + Sync + 'static) -> Self {
+        self.events = Events::new(handler);
+        self
+    }
+
+    pub fn build(self) -> Ev3Client {
+        let link_speed = if self.link_speed_cache {
+            LinkSpeed::user(&self.host)
+        } else {
+            LinkSpeed::default()
+        };
+        Ev3Client {
+            host: self.host,
+            discovery_port: self.discovery_port,
+            user: self.user,
+            password: Hasher::hash_password(&self.password),
+            compression: self.compression,
+            compression_level: self.compression_level,
+            compression_threads: self.compression_threads,
+            link_speed: Arc::new(link_speed),
+            hash_cache: self
+                .hash_cache
+                .then(HashCache::user)
+                .flatten()
+                .map(Arc::new),
+            hash_algorithm: self.hash_algorithm,
+            timeouts: self.timeouts,
+            address: OnceLock::new(),
+            events: self.events,
+        }
+    }
+}
+
+impl Ev3Client {
+    pub fn builder() -> Ev3ClientBuilder {
+        Ev3ClientBuilder::default()
+    }
+
+    /// Uploads a local file, skipping the transfer if the server already has it
+    pub fn upload(
+        &self,
+        path: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+    ) -> Result<UploadReport, ClientError> {
+        let (_, report) = self.deploy_file(path.as_ref(), remote.as_ref(), Action::Upload)?;
+        Ok(report)
+    }
+
+    /// Uploads the bytes as a file, skipping the transfer if the server already has it
+    pub fn upload_bytes(
+        &self,
+        bytes: &[u8],
+        remote: impl AsRef<Path>,
+    ) -> Result<UploadReport, ClientError> {
+        let session = self.session()?;
+        let hash = Hasher::digest_bytes(self.hash_algorithm(&session.hash_algorithms)?, bytes);
+        let size = bytes.len() as u64;
+        let compression = self.compression_for(|| Ok(bytes.to_vec()))?;
+        let payload = Payload {
+            reader: bytes,
+            hash,
+            size,
+            compression,
+        };
+        let (_, report) = self.deploy(session, payload, remote.as_ref(), Action::Upload)?;
+        Ok(report)
+    }
+
+    /// Runs a file that is already on the server
+    pub fn run(
+        &self,
+        remote: impl AsRef<Path>,
+        options: RunOptions,
+    ) -> Result<RunHandle, ClientError> {
+        let mut session = self.session()?;
+        let request = self.request(Action::Exec(options.program()), remote.as_ref(), None);
+        session.request(&request, &self.events)?;
+
+        RunHandle::spawn(session, options, self.events.clone())
+    }
+
+    /// Uploads a local file if the server doesn't have it yet and runs it
+    pub fn upload_and_run(
+        &self,
+        path: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        options: RunOptions,
+    ) -> Result<(UploadReport, RunHandle), ClientError> {
+        let action = Action::Run(options.program());
+        let (session, report) = self.deploy_file(path.as_ref(), remote.as_ref(), action)?;
+
+        Ok((
+            report,
+            RunHandle::spawn(session, options, self.events.clone())?,
+        ))
+    }
+
+    /// Reads the battery voltage and the connected motors and sensors
+    pub fn status(&self) -> Result<RobotStatus, ClientError> {
+        let mut session = self.session()?;
+        session.request(
+            &self.request(Action::Status, Path::new(""), None),
+            &self.events,
+        )?;
+        session.receive_status()
+    }
+
+    /// Removes a file from the server
+    pub fn delete(&self, remote: impl AsRef<Path>) -> Result<(), ClientError> {
+        let mut session = self.session()?;
+        session.request(
+            &self.request(Action::Delete, remote.as_ref(), None),
+            &self.events,
+        )?;
+        session.receive_result()
+    }
+
+    /// Lists the deployed and the kept earlier versions of a file on the server
+    pub fn history(&self, remote: impl AsRef<Path>) -> Result<FileHistory, ClientError> {
+        let mut session = self.session()?;
+        session.request(
+            &self.request(Action::History, remote.as_ref(), None),
+            &self.events,
+        )?;
+        session.receive_history()
+    }
+
+    /// Deploys a kept earlier version of a file again, the newest one or the one whose hash
+    /// starts with `to`, and returns it
+    pub fn rollback(
+        &self,
+        remote: impl AsRef<Path>,
+        to: Option<&str>,
+    ) -> Result<FileVersion, ClientError> {
+        let mut session = self.session()?;
+        let action = Action::Rollback(to.map(str::to_owned));
+        session.request(&self.request(action, remote.as_ref(), None), &self.events)?;
+        session.receive_rollback()
+    }
+
+    /// Removes the deployed files on the server that weren't uploaded, run or skipped for
+    /// `unused_days`, and the kept versions of files that weren't, least recently used first.
+    /// Only the files of the account are removed if it has its own root.
+    pub fn gc(&self, unused_days: u32) -> Result<GcReport, ClientError> {
+        let mut session = self.session()?;
+        session.request(
+            &self.request(Action::Gc(unused_days), Path::new(""), None),
+            &self.events,
+        )?;
+        session.receive_gc()
+    }
+
+    fn deploy_file(
+        &self,
+        path: &Path,
+        remote: &Path,
+        action: Action,
+    ) -> Result<(ClientSession, UploadReport), ClientError> {
+        if !path.is_file() {
+            return Err(ClientError::PathNotValid(path.to_owned()));
+        }
+
+        let session = self.session()?;
+        let algorithm = self.hash_algorithm(&session.hash_algorithms)?;
+
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let mut reader = BufReader::new(file);
+        let hash = self.hash_file(path, &metadata, algorithm, &mut reader)?;
+        let compression = self.compression_for(|| compression::sample(&mut reader, size))?;
+
+        let payload = Payload {
+            reader,
+            hash,
+            size,
+            compression,
+        };
+        self.deploy(session, payload, remote, action)
+    }
+
+    /// Sends the request and uploads the file if the server doesn't have it yet
+    fn deploy<R: Read>(
+        &self,
+        mut session: ClientSession,
+        payload: Payload<R>,
+        remote: &Path,
+        action: Action,
+    ) -> Result<(ClientSession, UploadReport), ClientError> {
+        let Payload {
+            mut reader,
+            hash,
+            size,
+            compression,
+        } = payload;
+        let request = Request {
+            size: Some(size),
+            compression,
+            ..self.request(action, remote, Some(hash))
+        };
+        let validation = session.request(&request, &self.events)?;
+
+        let skipped = validation.hash == MatchStatus::Match;
+        let bytes = if skipped {
+            info!("Remote file already up to date, no upload needed");
+            self.events.emit(ClientEvent::UploadSkipped {
+                remote_path: remote.to_owned(),
+            });
+            0
+        } else {
+            info!("Uploading file because remote hash did not match");
+            self.events.emit(ClientEvent::UploadStarted {
+                remote_path: remote.to_owned(),
+                size,
+            });
+
+            let started = Instant::now();
+            let mut progress = Progress::new(self.events.clone(), size);
+            let transferred =
+                session
+                    .transport
+                    .upload_file(&mut reader, compression, |transferred| {
+                        progress.update(transferred)
+                    })?;
+            self.link_speed
+                .record(transferred.written, transferred.wire_time);
+
+            self.events.emit(ClientEvent::UploadFinished {
+                remote_path: remote.to_owned(),
+                bytes: transferred.read,
+                wire_bytes: transferred.written,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+            transferred.read
+        };
+
+        let report = UploadReport {
+            remote_path: remote.to_owned(),
+            skipped,
+            bytes,
+        };
+        Ok((session, report))
+    }
+
+    pub(super) fn request(&self, action: Action, remote: &Path, hash: Option<Digest>) -> Request {
+        Request {
+            action,
+            path: remote.to_owned(),
+            hash,
+            size: None,
+            compression: Compression::None,
+            user: self.user.clone(),
+            password: self.password,
+        }
+    }
+
+    /// Hash of a local file, taken from the hash cache if the file didn't change.
+    ///
+    /// Leaves `reader` at the start of the file.
+    pub(super) fn hash_file<R: Read + Seek>(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        algorithm: HashAlgorithm,
+        reader: &mut R,
+    ) -> Result<Digest, io::Error> {
+        let cached = self
+            .hash_cache
+            .as_ref()
+            .and_then(|cache| cache.get(path, metadata, algorithm));
+        if let Some(hash) = cached {
+            return Ok(hash);
+        }
+
+        let hash = Hasher::hash_file(algorithm, reader)?;
+        reader.rewind()?;
+        if let Some(cache) = &self.hash_cache {
+            cache.insert(path, metadata, hash);
+        }
+        Ok(hash)
+    }
+
+    /// The configured hash algorithm if the server accepts it, otherwise the one it prefers
+    pub(super) fn hash_algorithm(
+        &self,
+        accepted: &[HashAlgorithm],
+    ) -> Result<HashAlgorithm, ClientError> {
+        let algorithm = match self.hash_algorithm {
+            Some(algorithm) if accepted.contains(&algorithm) => algorithm,
+            Some(algorithm) => return Err(ClientError::HashAlgorithmNotAccepted(algorithm)),
+            None => *accepted
+                .first()
+                .ok_or_else(|| io::Error::other("Server doesn't accept any hash algorithm"))?,
+        };
+
+        debug!("Using {algorithm} hashes");
+        Ok(algorithm)
+    }
+
+    /// Compression settings for an upload, sampling the file only in auto mode
+    pub(super) fn compression_for(
+        &self,
+        sample: impl FnOnce() -> Result<Vec<u8>, io::Error>,
+    ) -> Result<Compression, io::Error> {
+        compression::choose(
+            self.compression,
+            self.compression_level,
+            self.compression_threads,
+            &self.link_speed,
+            sample,
+        )
+    }
+
+    /// Address of the server, looked up the first time
+    pub(super) fn address(&self) -> Result<&str, ClientError> {
+        match self.address.get() {
+            Some(address) => Ok(address),
+            None => {
+                let address = resolve_host(&self.host, self.discovery_port)?;
+                Ok(self.address.get_or_init(|| address))
+            }
+        }
+    }
+
+    fn session(&self) -> Result<ClientSession, ClientError> {
+        let address = self.address()?;
+        let session = ClientSession::connect(address, self.timeouts);
+        match &session {
+            Ok(_) => self.events.emit(version_check(None)),
+            Err(ClientError::VersionMismatch(server_version)) => self
+                .events
+                .emit(version_check(Some(server_version.clone()))),
+            Err(_) => {}
+        }
+        session
+    }
+}
+
+pub(super) fn version_check(server_version: Option<String>) -> ClientEvent {
+    ClientEvent::VersionCheck {
+        client_version: VERSION.to_owned(),
+        compatible: server_version.is_none(),
+        server_version,
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    protocol::{
+        AuthStatus, ExitStatus, MatchStatus, OutputStream, PathStatus, SpaceStatus, Validation,
+    },
+    transport::TransferProgress,
+};
+use serde::Serialize;
+use std::{
+    fmt::Debug,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Something that happened while talking to the server, see `Ev3ClientBuilder::on_event`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// The server answered the version check
+    VersionCheck {
+        client_version: String,
+        /// Version of the server, only known if it doesn't match
+        #[serde(skip_serializing_if = "Option::is_none")]
+        server_version: Option<String>,
+        compatible: bool,
+    },
+    /// The server validated the request
+    Validation {
+        password: AuthStatus,
+        path: PathStatus,
+        hash: MatchStatus,
+        space: SpaceStatus,
+    },
+    UploadStarted {
+        remote_path: PathBuf,
+        /// Size of the file in bytes
+        size: u64,
+    },
+    UploadProgress {
+        /// Bytes of the file sent so far
+        sent: u64,
+        total: u64,
+        /// Bytes written to the connection, fewer than `sent` if compression pays off
+        wire_bytes: u64,
+    },
+    UploadFinished {
+        remote_path: PathBuf,
+        bytes: u64,
+        wire_bytes: u64,
+        duration_ms: u64,
+    },
+    /// The server already had the file
+    UploadSkipped { remote_path: PathBuf },
+    /// A chunk of output of the running program, invalid UTF-8 is replaced
+    Output { stream: OutputStream, data: String },
+    /// The program exited
+    Exit {
+        #[serde(flatten)]
+        status: ExitStatus,
+        success: bool,
+    },
+}
+
+impl From<Validation> for ClientEvent {
+    fn from(validation: Validation) -> Self {
+        ClientEvent::Validation {
+            password: validation.password,
+            path: validation.path,
+            hash: validation.hash,
+            space: validation.space,
+        }
+    }
+}
+
+impl From<ExitStatus> for ClientEvent {
+    fn from(status: ExitStatus) -> Self {
+        ClientEvent::Exit {
+            status,
+            success: status.success(),
+        }
+    }
+}
+
+type Handler = dyn Fn(&ClientEvent) + Send + Sync;
+
+/// Passes events to the handler registered on the client, if any
+#[derive(Clone, Default)]
+pub(super) struct Events(Option<Arc<Handler>>);
+
+impl Events {
+    pub(super) fn new(handler: impl Fn(&ClientEvent) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(handler)))
+    }
+
+    pub(super) fn emit(&self, event: ClientEvent) {
+        if let Some(handler) = &self.0 {
+            handler(&event);
+        }
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(super) fn output(&self, stream: OutputStream, chunk: &[u8]) {
+        if self.is_enabled() {
+            self.emit(ClientEvent::Output {
+                stream,
+                data: String::from_utf8_lossy(chunk).into_owned(),
+            });
+        }
+    }
+}
+
+impl Debug for Events {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Events")
+            .field(&self.0.as_ref().map(|_| "handler"))
+            .finish()
+    }
+}
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Turns the progress reported by the transport into `UploadProgress` events, at most one
+/// every `PROGRESS_INTERVAL` and one when the upload is complete
+pub(super) struct Progress {
+    events: Events,
+    total: u64,
+    last: Instant,
+    reported: TransferProgress,
+}
+
+impl Progress {
+    pub(super) fn new(events: Events, total: u64) -> Self {
+        Self {
+            events,
+            total,
+            last: Instant::now(),
+            reported: TransferProgress::default(),
+        }
+    }
+
+    pub(super) fn update(&mut self, transferred: TransferProgress) {
+        // Only new bytes are worth an event, not more time spent on the wire
+        if (transferred.read, transferred.written) == (self.reported.read, self.reported.written) {
+            return;
+        }
+
+        let done = transferred.read >= self.total;
+        if done || self.last.elapsed() >= PROGRESS_INTERVAL {
+            self.last = Instant::now();
+            self.reported = transferred;
+            self.events.emit(ClientEvent::UploadProgress {
+                sent: transferred.read,
+                total: self.total,
+                wire_bytes: transferred.written,
+            });
+        }
+    }
+}
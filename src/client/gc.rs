@@ -0,0 +1,34 @@
+use crate::{
+    client::clientsession::{ClientError, ClientSession},
+    protocol::{GcReport, GcResult},
+};
+
+impl ClientSession {
+    /// Reads the answer to a garbage collection request
+    pub(super) fn receive_gc(&mut self) -> Result<GcReport, ClientError> {
+        gc_result(self.transport.read_and_decode()?)
+    }
+}
+
+pub(super) fn gc_result(result: GcResult) -> Result<GcReport, ClientError> {
+    match result {
+        GcResult::Collected(report) => Ok(report),
+        GcResult::Failed(reason) => Err(ClientError::ActionFailed(reason)),
+    }
+}
+
+pub fn print_report(report: &GcReport) {
+    for file in &report.removed {
+        println!(
+            "Removed {} ({} bytes, unused for {} days)",
+            file.path.display(),
+            file.size,
+            file.unused_secs / 86_400
+        );
+    }
+    println!(
+        "{} files removed, {} bytes freed",
+        report.removed.len(),
+        report.freed
+    );
+}
@@ -0,0 +1,161 @@
+use crate::{
+    hash::modified_ns,
+    protocol::{Digest, HashAlgorithm},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+/// Hashes of local files, so files that didn't change since the last upload aren't read twice.
+///
+/// Entries are keyed by the canonical path and only used while the size and mtime match.
+/// The cache is loaded on first use and written back after every new hash.
+#[derive(Debug)]
+pub(super) struct HashCache {
+    file: PathBuf,
+    entries: Mutex<Option<HashMap<PathBuf, Entry>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_ns: u64,
+    hash: Digest,
+}
+
+impl Entry {
+    fn new(metadata: &Metadata, hash: Digest) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime_ns: modified_ns(metadata),
+            hash,
+        }
+    }
+}
+
+impl HashCache {
+    /// Cache in the user's cache directory, `None` if there is none
+    pub(super) fn user() -> Option<Self> {
+        user_cache_dir().map(|dir| Self::new(dir.join("ev3-runner").join("hashes.json")))
+    }
+
+    fn new(file: PathBuf) -> Self {
+        Self {
+            file,
+            entries: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn get(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        algorithm: HashAlgorithm,
+    ) -> Option<Digest> {
+        let path = path.canonicalize().ok()?;
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = *self.load(&mut entries).get(&path)?;
+
+        let hit = entry.hash.algorithm() == algorithm && Entry::new(metadata, entry.hash) == entry;
+        debug!(
+            "Hash cache {} for {}",
+            if hit { "hit" } else { "miss" },
+            path.display()
+        );
+        hit.then_some(entry.hash)
+    }
+
+    pub(super) fn insert(&self, path: &Path, metadata: &Metadata, hash: Digest) {
+        let Ok(path) = path.canonicalize() else {
+            return;
+        };
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = self.load(&mut entries);
+        entries.insert(path, Entry::new(metadata, hash));
+        // Forget files that are gone, so the cache doesn't grow forever
+        entries.retain(|path, _| path.is_file());
+
+        if let Err(e) = self.save(entries) {
+            warn!("Failed to save the hash cache {}: {e}", self.file.display());
+        }
+    }
+
+    fn load<'a>(
+        &self,
+        entries: &'a mut Option<HashMap<PathBuf, Entry>>,
+    ) -> &'a mut HashMap<PathBuf, Entry> {
+        entries.get_or_insert_with(|| match fs::read(&self.file) {
+            Ok(content) => serde_json::from_slice(&content)
+                .inspect_err(|e| warn!("Ignoring broken hash cache {}: {e}", self.file.display()))
+                .unwrap_or_default(),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to read hash cache {}: {e}", self.file.display());
+                }
+                HashMap::new()
+            }
+        })
+    }
+
+    fn save(&self, entries: &HashMap<PathBuf, Entry>) -> Result<(), io::Error> {
+        save_json(&self.file, entries)
+    }
+}
+
+/// Replaces `file` with `value` as JSON, creating its directory if needed
+pub(super) fn save_json<T: Serialize>(file: &Path, value: &T) -> Result<(), io::Error> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Other clients may be saving at the same time, the last one wins
+    let tmp = file.with_extension(format!("json.{}.tmp", process::id()));
+    fs::write(&tmp, serde_json::to_vec(value)?)?;
+    fs::rename(&tmp, file)
+}
+
+pub(super) fn user_cache_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_cache_is_reused_until_file_changes() {
+        let tmp = TempDir::new("hash-cache");
+        let dir = tmp.path();
+        let path = dir.join("program");
+        fs::write(&path, b"first").unwrap();
+
+        let hash = Digest::XxHash64(42);
+        let cache_file = dir.join("cache").join("hashes.json");
+        HashCache::new(cache_file.clone()).insert(&path, &fs::metadata(&path).unwrap(), hash);
+
+        let cache = HashCache::new(cache_file);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(
+            cache.get(&path, &metadata, HashAlgorithm::XxHash64),
+            Some(hash)
+        );
+        assert_eq!(cache.get(&path, &metadata, HashAlgorithm::Blake3), None);
+
+        fs::write(&path, b"second, longer").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(cache.get(&path, &metadata, HashAlgorithm::XxHash64), None);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{
+    client::clientsession::{ClientError, ClientSession},
+    protocol::{FileHistory, FileVersion, RollbackResult},
+};
+use std::path::Path;
+use tracing::debug;
+
+impl ClientSession {
+    /// Reads the history sent in response to a history request
+    pub(super) fn receive_history(&mut self) -> Result<FileHistory, ClientError> {
+        let history = self.transport.read_and_decode::<FileHistory>()?;
+        debug!("Received file history: {history:?}");
+
+        Ok(history)
+    }
+
+    /// Reads the answer to a rollback request
+    pub(super) fn receive_rollback(&mut self) -> Result<FileVersion, ClientError> {
+        rollback_result(self.transport.read_and_decode()?)
+    }
+}
+
+pub(super) fn rollback_result(result: RollbackResult) -> Result<FileVersion, ClientError> {
+    match result {
+        RollbackResult::Restored(version) => Ok(version),
+        RollbackResult::Failed(reason) => Err(ClientError::ActionFailed(reason)),
+    }
+}
+
+pub fn print_history(remote_path: &Path, history: &FileHistory) {
+    match &history.current {
+        Some(version) => println!("{}:\n  {}", remote_path.display(), line(version)),
+        None => println!("{}: deleted", remote_path.display()),
+    }
+
+    println!("Earlier versions:");
+    if history.versions.is_empty() {
+        println!("  none");
+    }
+    for version in &history.versions {
+        println!("  {}", line(version));
+    }
+}
+
+fn line(version: &FileVersion) -> String {
+    format!(
+        "{}  {:>10} bytes  {}",
+        version.hash,
+        version.size,
+        age(version.age_secs)
+    )
+}
+
+/// Age in the largest whole unit, like `3 h ago`
+fn age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs} s ago"),
+        60..3_600 => format!("{} min ago", secs / 60),
+        3_600..86_400 => format!("{} h ago", secs / 3_600),
+        _ => format!("{} d ago", secs / 86_400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_age() {
+        assert_eq!(age(59), "59 s ago");
+        assert_eq!(age(60), "1 min ago");
+        assert_eq!(age(7_200), "2 h ago");
+        assert_eq!(age(86_400 * 3 + 5), "3 d ago");
+    }
+}
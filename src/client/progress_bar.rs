@@ -0,0 +1,158 @@
+use crate::client::ClientEvent;
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const BAR_WIDTH: usize = 30;
+
+/// Draws a progress bar for uploads on stderr, meant to be passed to `Ev3ClientBuilder::on_event`
+#[derive(Debug, Default)]
+pub(super) struct ProgressBar {
+    started: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    pub(super) fn handle(&self, event: &ClientEvent) {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        let line = match event {
+            ClientEvent::UploadStarted { .. } => {
+                *started = Some(Instant::now());
+                return;
+            }
+            ClientEvent::UploadProgress {
+                sent,
+                total,
+                wire_bytes,
+            } => {
+                let elapsed = started.map(|s| s.elapsed()).unwrap_or_default();
+                format!("\r{}", render(*sent, *total, *wire_bytes, elapsed))
+            }
+            ClientEvent::UploadFinished {
+                bytes,
+                wire_bytes,
+                duration_ms,
+                ..
+            } => {
+                *started = None;
+                let elapsed = Duration::from_millis(*duration_ms);
+                format!("\r{}\n", summary(*bytes, *wire_bytes, elapsed))
+            }
+            _ => return,
+        };
+
+        let mut stderr = io::stderr().lock();
+        // Clear the rest of the previous line before drawing
+        let _ = write!(stderr, "{line}\x1b[K").and_then(|_| stderr.flush());
+    }
+}
+
+fn render(sent: u64, total: u64, wire_bytes: u64, elapsed: Duration) -> String {
+    let fraction = if total == 0 {
+        1.0
+    } else {
+        (sent as f64 / total as f64).min(1.0)
+    };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let rate = rate(sent, elapsed);
+
+    let mut line = format!(
+        "[{}{}] {:>3}% {}/{} {}/s",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        (fraction * 100.0) as u64,
+        format_bytes(sent),
+        format_bytes(total),
+        format_bytes(rate as u64),
+    );
+    if rate > 0.0 && sent < total {
+        let eta = Duration::from_secs_f64((total - sent) as f64 / rate);
+        line.push_str(&format!(" ETA {}", format_duration(eta)));
+    }
+    if let Some(ratio) = compression_ratio(sent, wire_bytes) {
+        line.push_str(&format!(" ratio {ratio:.2}x"));
+    }
+    line
+}
+
+fn summary(bytes: u64, wire_bytes: u64, elapsed: Duration) -> String {
+    let mut line = format!(
+        "Uploaded {} in {} ({}/s)",
+        format_bytes(bytes),
+        format_duration(elapsed),
+        format_bytes(rate(bytes, elapsed) as u64),
+    );
+    if let Some(ratio) = compression_ratio(bytes, wire_bytes) {
+        line.push_str(&format!(
+            ", {} on the wire ({ratio:.2}x)",
+            format_bytes(wire_bytes)
+        ));
+    }
+    line
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+/// Ratio of file bytes to bytes on the wire, `None` if nothing was saved
+fn compression_ratio(bytes: u64, wire_bytes: u64) -> Option<f64> {
+    (wire_bytes > 0 && wire_bytes < bytes).then(|| bytes as f64 / wire_bytes as f64)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_render() {
+        let line = render(512, 1024, 256, Duration::from_secs(1));
+        assert!(line.starts_with(&format!("[{}{}]  50%", "#".repeat(15), "-".repeat(15))));
+        assert!(line.contains("512 B/1.0 KiB 512 B/s"));
+        assert!(line.contains("ETA 1.0s"));
+        assert!(line.ends_with("ratio 2.00x"));
+    }
+
+    #[test]
+    fn test_render_without_compression() {
+        let line = render(1024, 1024, 1040, Duration::ZERO);
+        assert!(line.contains("100%"));
+        assert!(!line.contains("ETA"));
+        assert!(!line.contains("ratio"));
+    }
+}
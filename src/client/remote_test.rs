@@ -0,0 +1,152 @@
+mod junit;
+mod libtest;
+
+use crate::{
+    BUFFER_SIZE,
+    cli::TestArgs,
+    client::{
+        clientsession::ClientError,
+        ev3_client,
+        ev3client::Ev3Client,
+        run_handle::{Output, RunOptions},
+        runner::remote_path,
+    },
+    config::Config,
+    protocol::ExitStatus,
+};
+use libtest::{LibtestParser, Outcome, TestResult};
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+/// Flags passed to every test binary, required by the output parser
+const LIBTEST_ARGS: [&str; 2] = ["--test-threads=1", "--color=never"];
+
+/// Exit code used by libtest when tests failed
+const TESTS_FAILED: u8 = 101;
+
+/// Results of a single test binary
+#[derive(Debug)]
+pub struct SuiteResult {
+    pub name: String,
+    pub tests: Vec<TestResult>,
+    pub duration: Duration,
+}
+
+impl SuiteResult {
+    fn count(&self, outcome: Outcome) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| test.outcome == outcome)
+            .count()
+    }
+}
+
+/// Uploads and runs every test binary, prints a summary and optionally writes a JUnit report
+pub fn test(args: TestArgs) -> Result<ExitCode, ClientError> {
+    let config = Config::load()?;
+    let connection = config.connection(args.connection);
+    let client = ev3_client(&connection, &config.compression(args.compression));
+    let options = RunOptions::new()
+        .brickrun(args.brickrun || config.brickrun)
+        .args(LIBTEST_ARGS)
+        .args(args.args)
+        .stdout(Output::Piped);
+
+    let mut suites = Vec::new();
+    for binary in &args.binaries {
+        suites.push(run_binary(&client, binary, &options)?);
+    }
+
+    print_summary(&suites);
+
+    if let Some(path) = &args.junit {
+        junit::write_report(path, &suites)?;
+        info!("Wrote JUnit report to {}", path.display());
+    }
+
+    let failed = suites.iter().any(|suite| suite.count(Outcome::Failed) > 0);
+    Ok(if failed {
+        ExitCode::from(TESTS_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn run_binary(
+    client: &Ev3Client,
+    binary: &Path,
+    options: &RunOptions,
+) -> Result<SuiteResult, ClientError> {
+    let name = binary
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| ClientError::PathNotValid(binary.to_owned()))?;
+
+    let (_, mut handle) = client.upload_and_run(binary, remote_path(binary)?, options.clone())?;
+    let started = Instant::now();
+
+    let mut parser = LibtestParser::new();
+    if let Some(mut output) = handle.stdout() {
+        let mut stdout = io::stdout();
+        let mut buf = [0u8; BUFFER_SIZE];
+        loop {
+            let n = output.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            parser.feed(&buf[..n]);
+            stdout.write_all(&buf[..n])?;
+            stdout.flush()?;
+        }
+    }
+
+    let status = handle.wait()?;
+    let finished = Instant::now();
+
+    let mut tests = parser.finish(finished);
+    if !status.success() && !tests.iter().any(|test| test.outcome == Outcome::Failed) {
+        tests.push(crashed(&name, status));
+    }
+
+    Ok(SuiteResult {
+        name,
+        tests,
+        duration: finished.duration_since(started),
+    })
+}
+
+/// Result reported when the binary failed without a failing test, e.g. because it crashed
+fn crashed(name: &str, status: ExitStatus) -> TestResult {
+    error!("{name} exited with {status} without reporting a failed test");
+    TestResult {
+        name: name.to_owned(),
+        outcome: Outcome::Failed,
+        duration: Duration::ZERO,
+        output: format!("Test binary exited with {status}"),
+    }
+}
+
+fn print_summary(suites: &[SuiteResult]) {
+    let count = |outcome| suites.iter().map(|s| s.count(outcome)).sum::<usize>();
+    let duration: Duration = suites.iter().map(|suite| suite.duration).sum();
+
+    println!();
+    for suite in suites {
+        for test in suite.tests.iter().filter(|t| t.outcome == Outcome::Failed) {
+            println!("FAILED {} {}", suite.name, test.name);
+        }
+    }
+    println!(
+        "Summary: {} passed; {} failed; {} ignored; {} binaries; finished in {:.2}s",
+        count(Outcome::Passed),
+        count(Outcome::Failed),
+        count(Outcome::Ignored),
+        suites.len(),
+        duration.as_secs_f64()
+    );
+}
@@ -0,0 +1,95 @@
+use super::{
+    SuiteResult,
+    libtest::{Outcome, TestResult},
+};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self},
+    path::Path,
+};
+
+/// Writes the results as a JUnit XML report
+pub fn write_report(path: &Path, suites: &[SuiteResult]) -> io::Result<()> {
+    fs::write(path, report(suites))
+}
+
+fn report(suites: &[SuiteResult]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let count = |outcome| {
+        suites
+            .iter()
+            .map(|suite| suite.count(outcome))
+            .sum::<usize>()
+    };
+    let tests: usize = suites.iter().map(|suite| suite.tests.len()).sum();
+    let time: f64 = suites
+        .iter()
+        .map(|suite| suite.duration.as_secs_f64())
+        .sum();
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"ev3-runner\" tests=\"{tests}\" failures=\"{}\" skipped=\"{}\" time=\"{time:.3}\">",
+        count(Outcome::Failed),
+        count(Outcome::Ignored),
+    );
+
+    for suite in suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape(&suite.name),
+            suite.tests.len(),
+            suite.count(Outcome::Failed),
+            suite.count(Outcome::Ignored),
+            suite.duration.as_secs_f64(),
+        );
+        for test in &suite.tests {
+            write_test_case(&mut xml, &suite.name, test);
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn write_test_case(xml: &mut String, suite: &str, test: &TestResult) {
+    let _ = write!(
+        xml,
+        "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+        escape(&test.name),
+        escape(suite),
+        test.duration.as_secs_f64(),
+    );
+
+    match test.outcome {
+        Outcome::Passed => xml.push_str("/>\n"),
+        Outcome::Ignored => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+        Outcome::Failed => {
+            let _ = write!(
+                xml,
+                ">\n      <failure message=\"test failed\">{}</failure>\n    </testcase>\n",
+                escape(&test.output),
+            );
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab and newlines aren't allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
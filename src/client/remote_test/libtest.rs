@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    /// Captured output of failed tests
+    pub output: String,
+}
+
+/// Incremental parser for the pretty output format of libtest.
+///
+/// The test binary has to run with `--test-threads=1`. In that mode libtest prints
+/// `test <name> ... ` before a test starts and the result once it's done, which is
+/// used to measure the duration of every test.
+#[derive(Debug, Default)]
+pub struct LibtestParser {
+    line: Vec<u8>,
+    running: Option<(String, Instant)>,
+    results: Vec<TestResult>,
+    capture: Option<String>,
+    outputs: HashMap<String, String>,
+}
+
+impl LibtestParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.feed_at(bytes, Instant::now());
+    }
+
+    pub fn feed_at(&mut self, bytes: &[u8], now: Instant) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                self.parse_line(line.trim_end_matches('\r'), now);
+            } else {
+                self.line.push(byte);
+            }
+        }
+
+        // The start of a test is printed without a newline
+        let pending = String::from_utf8_lossy(&self.line);
+        if let Some(name) = pending
+            .strip_prefix("test ")
+            .and_then(|rest| rest.strip_suffix(" ... "))
+        {
+            let name = test_name(name);
+            if self
+                .running
+                .as_ref()
+                .is_none_or(|(running, _)| *running != name)
+            {
+                self.running = Some((name.to_owned(), now));
+            }
+        }
+    }
+
+    /// Finishes parsing and returns the results in the order the tests ran
+    pub fn finish(mut self, now: Instant) -> Vec<TestResult> {
+        if !self.line.is_empty() {
+            self.feed_at(b"\n", now);
+        }
+        self.end_capture();
+
+        for result in &mut self.results {
+            if let Some(output) = self.outputs.remove(&result.name) {
+                result.output = output;
+            }
+        }
+        self.results
+    }
+
+    fn parse_line(&mut self, line: &str, now: Instant) {
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|rest| rest.strip_suffix(" stdout ----"))
+        {
+            self.end_capture();
+            self.outputs
+                .insert(test_name(name).to_owned(), String::new());
+            self.capture = Some(test_name(name).to_owned());
+            return;
+        }
+
+        if self.capture.is_some() {
+            if line == "failures:" {
+                self.end_capture();
+            } else if let Some(output) = self
+                .capture
+                .as_ref()
+                .and_then(|name| self.outputs.get_mut(name))
+            {
+                output.push_str(line);
+                output.push('\n');
+            }
+            return;
+        }
+
+        let Some((name, result)) = line
+            .strip_prefix("test ")
+            .and_then(|rest| rest.split_once(" ... "))
+        else {
+            return;
+        };
+
+        let outcome = match result {
+            "ok" => Outcome::Passed,
+            "FAILED" => Outcome::Failed,
+            result if result.starts_with("ignored") => Outcome::Ignored,
+            _ => return,
+        };
+
+        let name = test_name(name).to_owned();
+        let duration = match self.running.take() {
+            Some((running, started)) if running == name => now.duration_since(started),
+            _ => Duration::ZERO,
+        };
+
+        self.results.push(TestResult {
+            name,
+            outcome,
+            duration,
+            output: String::new(),
+        });
+    }
+
+    fn end_capture(&mut self) {
+        if let Some(output) = self
+            .capture
+            .take()
+            .and_then(|name| self.outputs.get_mut(&name))
+        {
+            let trimmed = output.trim_end().len();
+            output.truncate(trimmed);
+        }
+    }
+}
+
+/// Strips annotations such as ` - should panic` from a test name
+fn test_name(name: &str) -> &str {
+    name.split(" - ").next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "\nrunning 4 tests\n\
+        test tests::adds ... ok\n\
+        test tests::panics - should panic ... ok\n\
+        test tests::slow ... ignored, takes too long\n\
+        test tests::fails ... FAILED\n\
+        \n\
+        failures:\n\
+        \n\
+        ---- tests::fails stdout ----\n\
+        thread 'tests::fails' panicked at src/lib.rs:10:9:\n\
+        assertion failed: false\n\
+        \n\
+        \n\
+        failures:\n    tests::fails\n\
+        \n\
+        test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out\n";
+
+    #[test]
+    fn test_parses_results() {
+        let mut parser = LibtestParser::new();
+        let now = Instant::now();
+        parser.feed_at(OUTPUT.as_bytes(), now);
+        let results = parser.finish(now);
+
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("tests::adds", Outcome::Passed),
+                ("tests::panics", Outcome::Passed),
+                ("tests::slow", Outcome::Ignored),
+                ("tests::fails", Outcome::Failed),
+            ]
+        );
+        assert_eq!(
+            results[3].output,
+            "thread 'tests::fails' panicked at src/lib.rs:10:9:\nassertion failed: false"
+        );
+    }
+
+    #[test]
+    fn test_measures_duration_across_chunks() {
+        let mut parser = LibtestParser::new();
+        let start = Instant::now();
+        parser.feed_at(b"running 1 test\ntest tests::sl", start);
+        parser.feed_at(b"ow ... ", start + Duration::from_millis(5));
+        parser.feed_at(b"ok\n", start + Duration::from_millis(105));
+
+        let results = parser.finish(start + Duration::from_secs(1));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].duration, Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    client::{
+        clientsession::{ClientError, ClientSession},
+        events::Events,
+    },
+    protocol::{ExitStatus, OutputStream, Program, RunEvent},
+};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+use tracing::{debug, info, warn};
+
+/// Where the output of a remote program goes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Write it to the stdout or stderr of this process
+    #[default]
+    Inherit,
+    /// Make it available through `RunHandle::stdout` or `RunHandle::stderr`
+    Piped,
+    /// Discard it
+    Null,
+}
+
+/// How to run a program on the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    pub brickrun: bool,
+    pub args: Vec<String>,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl RunOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start the program using brickrun
+    pub fn brickrun(mut self, brickrun: bool) -> Self {
+        self.brickrun = brickrun;
+        self
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn stdout(mut self, output: Output) -> Self {
+        self.stdout = output;
+        self
+    }
+
+    pub fn stderr(mut self, output: Output) -> Self {
+        self.stderr = output;
+        self
+    }
+
+    pub(super) fn program(&self) -> Program {
+        Program {
+            brickrun: self.brickrun,
+            args: self.args.clone(),
+        }
+    }
+}
+
+/// A program running on the server.
+///
+/// The output is received on a background thread. Piped output is buffered until it's
+/// read, so it doesn't have to be read for the program to make progress.
+pub struct RunHandle {
+    stdout: Option<OutputReader>,
+    stderr: Option<OutputReader>,
+    stream: TcpStream,
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<Result<ExitStatus, ClientError>>,
+}
+
+impl RunHandle {
+    pub(super) fn spawn(
+        session: ClientSession,
+        options: RunOptions,
+        events: Events,
+    ) -> Result<Self, ClientError> {
+        // Programs may stay silent for a long time, which is no reason to give up on them
+        session.transport.set_read_timeout(None)?;
+        let stream = session.transport.stream.try_clone()?;
+
+        let (stdout_sink, stdout) = Sink::new(options.stdout, OutputStream::Stdout);
+        let (stderr_sink, stderr) = Sink::new(options.stderr, OutputStream::Stderr);
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("run-output".to_owned())
+            .spawn({
+                let stopped = Arc::clone(&stopped);
+                move || receive(session, stdout_sink, stderr_sink, &events, &stopped)
+            })?;
+
+        Ok(Self {
+            stdout,
+            stderr,
+            stream,
+            stopped,
+            thread,
+        })
+    }
+
+    /// Takes the reader for the program's stdout, if it is piped
+    pub fn stdout(&mut self) -> Option<OutputReader> {
+        self.stdout.take()
+    }
+
+    /// Takes the reader for the program's stderr, if it is piped
+    pub fn stderr(&mut self) -> Option<OutputReader> {
+        self.stderr.take()
+    }
+
+    /// Stops the program by closing the connection
+    pub fn kill(&self) -> Result<(), ClientError> {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    /// Waits for the program to exit
+    pub fn wait(self) -> Result<ExitStatus, ClientError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Output thread panicked").into()))
+    }
+}
+
+fn receive(
+    mut session: ClientSession,
+    mut stdout: Sink,
+    mut stderr: Sink,
+    events: &Events,
+    stopped: &AtomicBool,
+) -> Result<ExitStatus, ClientError> {
+    loop {
+        let event = match session.transport.read_and_decode::<RunEvent>() {
+            Ok(event) => event,
+            Err(_) if stopped.load(Ordering::SeqCst) => return Err(ClientError::Stopped),
+            Err(e) => return Err(e.into()),
+        };
+
+        match event {
+            RunEvent::Output(stream, chunk) => {
+                events.output(stream, &chunk);
+                match stream {
+                    OutputStream::Stdout => stdout.write(chunk)?,
+                    OutputStream::Stderr => stderr.write(chunk)?,
+                }
+            }
+            RunEvent::Exit(status) => {
+                if status.success() {
+                    info!("Program exited with {status}");
+                } else {
+                    warn!("Program exited with {status}");
+                }
+                events.emit(status.into());
+                session.transport.stream.shutdown(Shutdown::Both).ok();
+                return Ok(status);
+            }
+        }
+    }
+}
+
+enum Sink {
+    Inherit(OutputStream),
+    Piped(Sender<Vec<u8>>),
+    Null,
+}
+
+impl Sink {
+    fn new(output: Output, stream: OutputStream) -> (Self, Option<OutputReader>) {
+        match output {
+            Output::Inherit => (Sink::Inherit(stream), None),
+            Output::Null => (Sink::Null, None),
+            Output::Piped => {
+                let (sender, receiver) = mpsc::channel();
+                let reader = OutputReader {
+                    receiver,
+                    chunk: Vec::new(),
+                    position: 0,
+                };
+                (Sink::Piped(sender), Some(reader))
+            }
+        }
+    }
+
+    fn write(&mut self, chunk: Vec<u8>) -> Result<(), io::Error> {
+        match self {
+            Sink::Inherit(OutputStream::Stdout) => write_flush(&mut io::stdout(), &chunk),
+            Sink::Inherit(OutputStream::Stderr) => write_flush(&mut io::stderr(), &chunk),
+            Sink::Piped(sender) => {
+                if sender.send(chunk).is_err() {
+                    debug!("Output reader was dropped, discarding output");
+                }
+                Ok(())
+            }
+            Sink::Null => Ok(()),
+        }
+    }
+}
+
+fn write_flush<W: Write>(output: &mut W, chunk: &[u8]) -> Result<(), io::Error> {
+    output
+        .write_all(chunk)
+        .inspect_err(|e| warn!("Failed to write to the output: {e}"))?;
+    output
+        .flush()
+        .inspect_err(|e| warn!("Failed to flush the output: {e}"))
+}
+
+/// Reader for piped output of a remote program, returns EOF once the program exited
+pub struct OutputReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for OutputReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
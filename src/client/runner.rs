@@ -0,0 +1,55 @@
+use crate::{
+    cli::RunnerArgs,
+    client::{clientsession::ClientError, ev3_client, run_handle::RunOptions},
+    config::Config,
+    hash::Hasher,
+    protocol::ExitStatus,
+};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Uploads and runs a binary the way cargo invokes a target runner
+pub fn runner(args: RunnerArgs) -> Result<ExitStatus, ClientError> {
+    let config = Config::load()?;
+    let connection = config.connection(args.connection);
+    let client = ev3_client(&connection, &config.compression(args.compression));
+
+    let remote_path = remote_path(&args.binary)?;
+    info!(
+        "Running {} as {}",
+        args.binary.display(),
+        remote_path.display()
+    );
+
+    let options = RunOptions::new()
+        .brickrun(args.brickrun || config.brickrun)
+        .args(args.args);
+    let (_, handle) = client.upload_and_run(&args.binary, remote_path, options)?;
+
+    handle.wait()
+}
+
+/// Remote file name for a local artifact.
+///
+/// The name includes a hash of the canonical local path, so binaries with the same
+/// name from different projects or profiles don't overwrite each other, while
+/// rebuilds of the same artifact still reuse the remote file.
+pub(super) fn remote_path(binary: &Path) -> Result<PathBuf, ClientError> {
+    let canonical = binary
+        .canonicalize()
+        .map_err(|_| ClientError::PathNotValid(binary.to_owned()))?;
+    let name = canonical
+        .file_name()
+        .ok_or_else(|| ClientError::PathNotValid(binary.to_owned()))?
+        .to_string_lossy();
+
+    let path_hash = Hasher::hash_bytes(canonical.as_os_str().as_encoded_bytes());
+    let remote = PathBuf::from(format!("{name}-{:08x}", path_hash as u32));
+    debug!(
+        "Remote path for {}: {}",
+        canonical.display(),
+        remote.display()
+    );
+
+    Ok(remote)
+}
@@ -0,0 +1,55 @@
+use crate::client::clientsession::{ClientError, ClientSession};
+use crate::protocol::RobotStatus;
+use tracing::debug;
+
+impl ClientSession {
+    /// Reads the status sent in response to a status request
+    pub(super) fn receive_status(&mut self) -> Result<RobotStatus, ClientError> {
+        let status = self.transport.read_and_decode::<RobotStatus>()?;
+        debug!("Received robot status: {status:?}");
+
+        Ok(status)
+    }
+}
+
+pub fn print_status(status: &RobotStatus) {
+    for supply in &status.power_supplies {
+        let voltage = supply
+            .voltage
+            .map(|uv| format!("{:.2} V", uv as f64 / 1_000_000.0))
+            .unwrap_or_else(|| "unknown voltage".to_owned());
+        let current = supply
+            .current
+            .map(|ua| format!(", {:.0} mA", ua as f64 / 1_000.0))
+            .unwrap_or_default();
+        println!("Power supply {}: {voltage}{current}", supply.name);
+    }
+
+    println!("Motors:");
+    if status.motors.is_empty() {
+        println!("  none");
+    }
+    for motor in &status.motors {
+        println!("  {:<6} {}", port_name(&motor.address), motor.driver_name);
+    }
+
+    println!("Sensors:");
+    if status.sensors.is_empty() {
+        println!("  none");
+    }
+    for sensor in &status.sensors {
+        match &sensor.mode {
+            Some(mode) => println!(
+                "  {:<6} {} ({mode})",
+                port_name(&sensor.address),
+                sensor.driver_name
+            ),
+            None => println!("  {:<6} {}", port_name(&sensor.address), sensor.driver_name),
+        }
+    }
+}
+
+/// Strips the `ev3-ports:` prefix from a device address
+fn port_name(address: &str) -> &str {
+    address.strip_prefix("ev3-ports:").unwrap_or(address)
+}
@@ -0,0 +1,148 @@
+use crate::{
+    VERSION,
+    client::{
+        clientsession::{ClientError, ClientSession},
+        ev3client::Ev3Client,
+        events::{ClientEvent, Progress},
+    },
+    protocol::{
+        Compression, UPDATE_HEADER, UpdateRequest, UpdateResponse, VersionHeader, VersionResponse,
+        VersionStatus,
+    },
+};
+use sha2::{Digest as _, Sha256};
+use std::{
+    fs::File,
+    io::{self, BufReader, Seek},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+/// How long the server may take to come back with the new binary
+const RESTART_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Ev3Client {
+    /// Replaces the server binary with `binary` and waits until the server runs it, returns
+    /// the version it runs then.
+    ///
+    /// Works whatever version the server runs, as long as it supports updates. If the server
+    /// has accounts, the account needs the `update` permission.
+    pub fn update_server(&self, binary: impl AsRef<Path>) -> Result<String, ClientError> {
+        let binary = binary.as_ref();
+        if !binary.is_file() {
+            return Err(ClientError::PathNotValid(binary.to_owned()));
+        }
+
+        let file = File::open(binary)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut hasher = Sha256::new();
+        io::copy(&mut reader, &mut hasher)?;
+        reader.rewind()?;
+
+        let address = self.address()?;
+        let mut transport = ClientSession::open(address, self.timeouts)?;
+        transport.encode_and_write(VersionHeader(UPDATE_HEADER.to_owned()))?;
+        if let VersionResponse(VersionStatus::Mismatch(server_version)) =
+            transport.read_and_decode()?
+        {
+            return Err(ClientError::UpdateNotSupported(server_version));
+        }
+
+        transport.encode_and_write(UpdateRequest {
+            user: self.user.clone(),
+            password: self.password,
+            size,
+            sha256: hasher.finalize().into(),
+        })?;
+        let executable = match transport.read_and_decode()? {
+            UpdateResponse::Ready { executable } => executable,
+            response => return Err(refused(response)),
+        };
+        transport.set_deadline(None);
+        transport.set_read_timeout(self.timeouts.idle)?;
+        transport.set_write_timeout(self.timeouts.idle)?;
+
+        info!("Uploading {} to {}", binary.display(), executable.display());
+        self.events.emit(ClientEvent::UploadStarted {
+            remote_path: executable.clone(),
+            size,
+        });
+        let started = Instant::now();
+        let mut progress = Progress::new(self.events.clone(), size);
+        let transferred = transport.upload_file(&mut reader, Compression::None, |transferred| {
+            progress.update(transferred)
+        })?;
+        self.events.emit(ClientEvent::UploadFinished {
+            remote_path: executable,
+            bytes: transferred.read,
+            wire_bytes: transferred.written,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+
+        let (version, previous) = match transport.read_and_decode()? {
+            UpdateResponse::Installed { version, previous } => (version, previous),
+            response => return Err(refused(response)),
+        };
+        drop(transport);
+
+        info!("Server installed ev3-runner {version}, waiting for it to restart");
+        self.wait_for_restart(&version, &previous)?;
+        Ok(version)
+    }
+
+    /// Queries the status until the server answers again, and fails if it went back to the
+    /// previous version. A rollback can't be told apart if both versions are the same.
+    fn wait_for_restart(&self, version: &str, previous: &str) -> Result<(), ClientError> {
+        let started = Instant::now();
+        loop {
+            // A complete request, so the server doesn't log a broken connection
+            let running = match self.status() {
+                Ok(_) => Some(VERSION.to_owned()),
+                Err(ClientError::VersionMismatch(running)) => Some(running),
+                Err(e) => {
+                    debug!("Server isn't back yet: {e}");
+                    None
+                }
+            };
+
+            match running {
+                Some(running) if running == version => return Ok(()),
+                Some(running) if running == previous => {
+                    return Err(ClientError::UpdateRolledBack(running));
+                }
+                Some(running) => {
+                    return Err(ClientError::UpdateFailed(format!(
+                        "The server runs {running} instead"
+                    )));
+                }
+                None if started.elapsed() >= RESTART_TIMEOUT => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "The server didn't come back after the update",
+                    )
+                    .into());
+                }
+                None => thread::sleep(RETRY_INTERVAL),
+            }
+        }
+    }
+}
+
+/// Error for an answer that isn't the expected one
+fn refused(response: UpdateResponse) -> ClientError {
+    match response {
+        UpdateResponse::WrongPassword => ClientError::PasswordNotValid,
+        UpdateResponse::LockedOut { retry_after_secs } => {
+            ClientError::LockedOut(Duration::from_secs(retry_after_secs))
+        }
+        UpdateResponse::Forbidden => ClientError::Forbidden,
+        UpdateResponse::Failed(reason) => ClientError::UpdateFailed(reason),
+        UpdateResponse::Ready { .. } | UpdateResponse::Installed { .. } => {
+            ClientError::UpdateFailed(format!("Unexpected answer {response:?}"))
+        }
+    }
+}
@@ -1,33 +1,47 @@
-use crate::client::clientsession::{ClientError, ClientSession};
-use crate::protocol::{MatchStatus, PathStatus, Validation};
-use std::fs::File;
-use std::io::BufReader;
+use crate::client::clientsession::ClientError;
+use crate::protocol::{AuthStatus, PathStatus, SpaceStatus, Validation};
+use std::time::Duration;
 use tracing::{error, info};
 
-impl ClientSession {
-    pub(super) fn validation(&mut self, mut reader: BufReader<File>) -> Result<(), ClientError> {
-        let validation = self.transport.read_and_decode::<Validation>()?;
-
-        if validation.password == MatchStatus::Mismatch {
+/// Checks that the password was accepted and, for requests about a file, that the remote path
+/// is valid and the server has room for an upload
+pub(super) fn check_validation(
+    validation: Validation,
+    uses_file: bool,
+) -> Result<Validation, ClientError> {
+    match validation.password {
+        AuthStatus::Match => {}
+        AuthStatus::Mismatch => {
             error!("Wrong password");
             return Err(ClientError::PasswordNotValid);
         }
-        info!("Correct password");
-
-        if validation.path != PathStatus::Valid {
-            error!("Remote path is not valid: {}", validation.path);
-            return Err(validation.path.into());
+        AuthStatus::LockedOut { retry_after_secs } => {
+            error!("The server doesn't check passwords from this address for {retry_after_secs}s");
+            return Err(ClientError::LockedOut(Duration::from_secs(
+                retry_after_secs,
+            )));
         }
-        info!("Remote path is valid");
-
-        if validation.hash == MatchStatus::Mismatch {
-            info!("Uploading file because remote hash did not match");
-            self.transport
-                .upload_file(&mut reader, self.args.compression)?;
-        } else {
-            info!("Remote file already up to date, no upload needed");
+        AuthStatus::Forbidden => {
+            error!("The account isn't allowed to do this");
+            return Err(ClientError::Forbidden);
         }
+    }
+    info!("Correct password");
 
-        Ok(())
+    if !uses_file {
+        return Ok(validation);
     }
+
+    if validation.path != PathStatus::Valid {
+        error!("Remote path is not valid: {}", validation.path);
+        return Err(validation.path.into());
+    }
+    info!("Remote path is valid");
+
+    if validation.space != SpaceStatus::Available {
+        error!("The server refused the upload: {}", validation.space);
+        return Err(ClientError::UploadRefused(validation.space));
+    }
+
+    Ok(validation)
 }
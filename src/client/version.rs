@@ -1,6 +1,6 @@
 use crate::VERSION;
 use crate::client::clientsession::{ClientError, ClientSession};
-use crate::protocol::{VersionHeader, VersionResponse, VersionStatus};
+use crate::protocol::{HashAlgorithms, VersionHeader, VersionResponse, VersionStatus};
 use tracing::{debug, error};
 
 impl ClientSession {
@@ -9,13 +9,22 @@ impl ClientSession {
             .encode_and_write(VersionHeader(VERSION.to_owned()))?;
 
         let version_response = self.transport.read_and_decode::<VersionResponse>()?;
-        if let VersionResponse(VersionStatus::Mismatch(server_version)) = version_response {
-            error!("Server version ({server_version}) does not match client version ({VERSION})");
-            return Err(ClientError::VersionMismatch(server_version));
-        };
-
-        debug!("No version mismatch");
+        check_version_response(version_response)?;
 
+        let HashAlgorithms(hash_algorithms) = self.transport.read_and_decode()?;
+        debug!("Server accepts {hash_algorithms:?} hashes");
+        self.hash_algorithms = hash_algorithms;
         Ok(())
     }
 }
+
+pub(super) fn check_version_response(response: VersionResponse) -> Result<(), ClientError> {
+    if let VersionResponse(VersionStatus::Mismatch(server_version)) = response {
+        error!("Server version ({server_version}) does not match client version ({VERSION})");
+        return Err(ClientError::VersionMismatch(server_version));
+    };
+
+    debug!("No version mismatch");
+
+    Ok(())
+}
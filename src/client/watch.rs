@@ -0,0 +1,87 @@
+use crate::{
+    cli::ClientArgs,
+    client::{
+        args_client, clientsession::ClientError, remote_path, run_handle::RunHandle,
+        run_handle::RunOptions,
+    },
+};
+use std::{
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{debug, error, info};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Size and modification time of the watched file, `None` while it doesn't exist
+type FileState = Option<(u64, SystemTime)>;
+
+/// Deploys the file and redeploys it every time it changes, until the process is interrupted.
+///
+/// With `run` set, the program is started after every upload and the previous run is stopped.
+pub fn watch(args: &ClientArgs, run: Option<RunOptions>) -> Result<(), ClientError> {
+    let client = args_client(args);
+    let remote_path = remote_path(args)?;
+    let debounce = Duration::from_millis(args.debounce);
+
+    let mut state = file_state(&args.filepath);
+    loop {
+        let running = match &run {
+            Some(options) => client
+                .upload_and_run(&args.filepath, &remote_path, options.clone())
+                .map(|(_, handle)| Some(handle)),
+            None => client.upload(&args.filepath, &remote_path).map(|_| None),
+        };
+        let running = running.unwrap_or_else(|e| {
+            error!("Deployment failed: {e}");
+            None
+        });
+
+        info!("Watching {} for changes", args.filepath.display());
+        state = wait_for_change(&args.filepath, state, debounce);
+        info!("{} changed, redeploying", args.filepath.display());
+
+        if let Some(handle) = running {
+            stop(handle);
+        }
+    }
+}
+
+/// Closing the connection makes the server stop the program
+fn stop(handle: RunHandle) {
+    if let Err(e) = handle.kill() {
+        debug!("Failed to stop the program: {e}");
+    }
+    match handle.wait() {
+        Ok(status) => debug!("Program had already exited with {status}"),
+        Err(e) => debug!("Output stream ended: {e}"),
+    }
+}
+
+/// Blocks until the file differs from `previous` and then stayed unchanged for `debounce`
+fn wait_for_change(path: &Path, previous: FileState, debounce: Duration) -> FileState {
+    let mut current = previous;
+    while current == previous {
+        thread::sleep(POLL_INTERVAL);
+        current = file_state(path);
+    }
+
+    let mut stable_since = Instant::now();
+    loop {
+        thread::sleep(POLL_INTERVAL.min(debounce));
+        let state = file_state(path);
+        if state != current {
+            current = state;
+            stable_since = Instant::now();
+        } else if current.is_some() && stable_since.elapsed() >= debounce {
+            return current;
+        }
+    }
+}
+
+fn file_state(path: &Path) -> FileState {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
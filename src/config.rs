@@ -0,0 +1,110 @@
+use crate::cli::{
+    CompressionArgs, ConnectionArgs, DEFAULT_DISCOVERY_PORT, DEFAULT_HOST, DEFAULT_PASSWORD,
+    EnvConnectionArgs,
+};
+use crate::protocol::HashAlgorithm;
+use serde::Deserialize;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+/// Name of the project-local config file, searched in the current directory and its parents
+pub const CONFIG_FILE_NAME: &str = "ev3-runner.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Failed to parse config file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+/// Client settings read from a config file, used where no command-line option or
+/// environment variable is given
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub discovery_port: Option<u16>,
+    pub hash: Option<HashAlgorithm>,
+    pub brickrun: bool,
+    pub compression: bool,
+    pub auto_compression: bool,
+    pub compression_level: Option<i32>,
+    pub compression_threads: Option<u32>,
+}
+
+impl Config {
+    /// Loads the first config file found, or the default config if there is none.
+    ///
+    /// The lookup order is `$EV3_RUNNER_CONFIG`, `ev3-runner.toml` in the current
+    /// directory or one of its parents, and finally `ev3-runner/config.toml` in the
+    /// user's config directory.
+    pub fn load() -> Result<Self, ConfigError> {
+        let Some(path) = Self::find() else {
+            debug!("No config file found");
+            return Ok(Self::default());
+        };
+
+        Self::from_file(&path)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        debug!("Loading config file {}", path.display());
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+    }
+
+    /// Fills in the connection options not given on the command line or in the environment
+    pub fn connection(&self, args: EnvConnectionArgs) -> ConnectionArgs {
+        ConnectionArgs {
+            host: args
+                .host
+                .or_else(|| self.host.clone())
+                .unwrap_or_else(|| DEFAULT_HOST.to_owned()),
+            user: args.user.or_else(|| self.user.clone()),
+            password: args
+                .password
+                .or_else(|| self.password.clone())
+                .unwrap_or_else(|| DEFAULT_PASSWORD.to_owned()),
+            discovery_port: self.discovery_port.unwrap_or(DEFAULT_DISCOVERY_PORT),
+            hash: args.hash.or(self.hash),
+            timeouts: args.timeouts,
+        }
+    }
+
+    /// Fills in the compression options not given on the command line
+    pub fn compression(&self, args: CompressionArgs) -> CompressionArgs {
+        // An explicit choice on the command line wins over the mode in the config file
+        let explicit = args.compression || args.auto_compression;
+        CompressionArgs {
+            compression: args.compression || (!explicit && self.compression),
+            auto_compression: args.auto_compression || (!explicit && self.auto_compression),
+            compression_level: args.compression_level.or(self.compression_level),
+            compression_threads: args.compression_threads.or(self.compression_threads),
+        }
+    }
+
+    fn find() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("EV3_RUNNER_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+
+        let cwd = env::current_dir().ok()?;
+        cwd.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .chain(user_config_dir().map(|dir| dir.join("ev3-runner").join("config.toml")))
+            .find(|path| path.is_file())
+    }
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
@@ -1,9 +1,13 @@
-use crate::BUFFER_SIZE;
-use sha2::{Digest, Sha256};
+use crate::{
+    BUFFER_SIZE,
+    protocol::{Digest, HashAlgorithm},
+};
+use sha2::{Digest as _, Sha256};
 use std::{
-    fs::File,
+    fs::Metadata,
     hash::Hasher as _,
-    io::{BufReader, Error, Read},
+    io::{Error, Read, Write},
+    time::UNIX_EPOCH,
 };
 use twox_hash::XxHash64;
 
@@ -12,8 +16,8 @@ pub struct Hasher;
 impl Hasher {
     const SEED: u64 = 4167; // Just a random number
 
-    pub fn hash_file(file: &mut BufReader<File>) -> Result<u64, Error> {
-        let mut hasher = XxHash64::with_seed(Self::SEED);
+    pub fn hash_file<R: Read>(algorithm: HashAlgorithm, file: &mut R) -> Result<Digest, Error> {
+        let mut state = State::new(algorithm);
 
         let mut buf = [0u8; BUFFER_SIZE];
 
@@ -22,10 +26,21 @@ impl Hasher {
             if n == 0 {
                 break;
             }
-            hasher.write(&buf[..n]);
+            state.update(&buf[..n]);
         }
 
-        Ok(hasher.finish())
+        Ok(state.finish())
+    }
+
+    pub fn digest_bytes(algorithm: HashAlgorithm, bytes: &[u8]) -> Digest {
+        let mut state = State::new(algorithm);
+        state.update(bytes);
+        state.finish()
+    }
+
+    /// Fast non-cryptographic hash, e.g. for naming files
+    pub fn hash_bytes(bytes: &[u8]) -> u64 {
+        XxHash64::oneshot(Self::SEED, bytes)
     }
 
     pub fn hash_password(password: &str) -> [u8; 32] {
@@ -34,3 +49,114 @@ impl Hasher {
         hasher.finalize().into()
     }
 }
+
+enum State {
+    XxHash64(XxHash64),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl State {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::XxHash64 => State::XxHash64(XxHash64::with_seed(Hasher::SEED)),
+            HashAlgorithm::Sha256 => State::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => State::Blake3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            State::XxHash64(hasher) => hasher.write(bytes),
+            State::Sha256(hasher) => hasher.update(bytes),
+            State::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    fn finish(self) -> Digest {
+        match self {
+            State::XxHash64(hasher) => Digest::XxHash64(hasher.finish()),
+            State::Sha256(hasher) => Digest::Sha256(hasher.finalize().into()),
+            State::Blake3(hasher) => Digest::Blake3(hasher.finalize().into()),
+        }
+    }
+}
+
+/// Modification time in nanoseconds since the epoch, 0 if the platform doesn't provide it.
+///
+/// Used together with the size to tell whether a hashed file changed.
+pub fn modified_ns(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_nanos() as u64)
+}
+
+/// Hashes everything written through it, like `Hasher::hash_file` would
+pub struct HashWriter<W> {
+    inner: W,
+    state: State,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W, algorithm: HashAlgorithm) -> Self {
+        Self {
+            inner,
+            state: State::new(algorithm),
+        }
+    }
+
+    pub fn finish(self) -> Digest {
+        self.state.finish()
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write(buf)?;
+        self.state.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_matches_file_hash() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for algorithm in [
+            HashAlgorithm::XxHash64,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+        ] {
+            let mut writer = HashWriter::new(Vec::new(), algorithm);
+            writer.write_all(&content).unwrap();
+
+            let digest = Hasher::hash_file(algorithm, &mut content.as_slice()).unwrap();
+            assert_eq!(digest.algorithm(), algorithm);
+            assert_eq!(writer.finish(), digest);
+            assert_eq!(Hasher::digest_bytes(algorithm, &content), digest);
+        }
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            Hasher::digest_bytes(HashAlgorithm::Sha256, b"").to_string(),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            Hasher::digest_bytes(HashAlgorithm::Blake3, b"").to_string(),
+            "blake3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+}
@@ -1,22 +1,38 @@
 pub mod cli;
 mod client;
+mod config;
 mod hash;
 pub mod protocol;
 mod server;
+#[cfg(test)]
+mod test_util;
 mod transport;
 
-pub use crate::client::client;
-pub use server::server;
+#[cfg(feature = "async")]
+pub use crate::client::{AsyncEv3Client, AsyncRunHandle};
+pub use crate::client::{
+    ClientError, ClientEvent, CompressionMode, DiscoveredServer, Ev3Client, Ev3ClientBuilder,
+    Output, OutputReader, RunHandle, RunOptions, UploadReport, cargo_ev3, client, discover,
+};
+pub use server::{
+    Account, Cidr, CidrError, Ev3Server, Ev3ServerBuilder, HookEvent, Permission, ShutdownHandle,
+    load_accounts, server,
+};
 
 const BUFFER_SIZE: usize = 16 * 1024;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Logging
+use std::io::IsTerminal;
 use tracing::Level;
 use tracing_subscriber::fmt::SubscriberBuilder;
 
 pub fn setup_logging(verbosity: u8) {
-    let subscriber = SubscriberBuilder::default();
+    // Logs go to stderr, so they don't mix with program output or JSON events on stdout
+    // No colors when logging to a file, e.g. the log file of the daemon
+    let subscriber = SubscriberBuilder::default()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
     let subscriber = match verbosity {
         0 => subscriber.with_max_level(Level::WARN),
         1 => subscriber.with_max_level(Level::INFO),
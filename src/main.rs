@@ -2,17 +2,21 @@ use ev3_runner::{
     cli::{Cli, Commands, Parser},
     client, server, setup_logging,
 };
+use std::process::ExitCode;
 use tracing::error;
 
-fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<ExitCode> {
     let cli = Cli::parse();
     setup_logging(cli.verbose);
 
-    match cli.command {
-        Commands::Server(config) => server(config)?,
+    let exit_code = match cli.command {
+        Commands::Server(config) => {
+            server(config)?;
+            ExitCode::SUCCESS
+        }
         Commands::Client(config) => client(config)
             .inspect_err(|e| error!("Error while handling connection with the server: {e}"))?,
-    }
+    };
 
-    Ok(())
+    Ok(exit_code)
 }
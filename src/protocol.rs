@@ -1,5 +1,9 @@
 use bincode::{Decode, Encode};
-use std::{fmt::Debug, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Debug, Display},
+    path::PathBuf,
+};
 
 #[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct VersionHeader(pub String);
@@ -13,12 +17,82 @@ pub enum VersionStatus {
     Mismatch(String),
 }
 
+/// Hash algorithms the server accepts, most preferred first, sent after a version match
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct HashAlgorithms(pub Vec<HashAlgorithm>);
+
+/// Sent as the client version to replace the server binary instead of making a request.
+///
+/// A server that supports updates answers `VersionStatus::Match` and expects an
+/// `UpdateRequest`. None of the update messages may ever change, so any client can update
+/// any server, whatever their versions are.
+pub const UPDATE_HEADER: &str = "ev3-runner/update-server";
+
+#[derive(Decode, Encode, PartialEq, Eq, Clone)]
+pub struct UpdateRequest {
+    pub user: Option<String>,
+    pub password: [u8; 32],
+    /// Size of the new binary, which follows as a file upload without compression
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+impl Debug for UpdateRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpdateRequest")
+            .field("user", &self.user)
+            .field("password", &"REDACTED")
+            .field("size", &self.size)
+            .field("sha256", &Digest::Sha256(self.sha256).to_string())
+            .finish()
+    }
+}
+
+/// Answer to an `UpdateRequest`, `Ready` before the upload and `Installed` or `Failed` after it
+#[derive(Debug, Decode, Encode, PartialEq, Eq, Clone)]
+pub enum UpdateResponse {
+    /// Send the binary, it replaces `executable` on the server
+    Ready {
+        executable: PathBuf,
+    },
+    WrongPassword,
+    LockedOut {
+        retry_after_secs: u64,
+    },
+    Forbidden,
+    /// The binary was installed and the server restarts with it
+    Installed {
+        version: String,
+        previous: String,
+    },
+    Failed(String),
+}
+
+/// Payload of the UDP broadcast used to find servers on the local network
+pub const DISCOVERY_MAGIC: &[u8] = b"ev3-runner/discover";
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct DiscoveryResponse {
+    pub name: String,
+    pub hostname: String,
+    /// TCP port the server accepts connections on
+    pub port: u16,
+    pub version: String,
+}
+
 #[derive(Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Request {
     pub action: Action,
     pub path: PathBuf,
-    pub hash: u64,
-    pub use_compression: bool,
+    /// Hash of the file, only needed for actions that upload it
+    pub hash: Option<Digest>,
+    /// Size of the file before compression, only needed for actions that upload it. The server
+    /// refuses to receive more.
+    pub size: Option<u64>,
+    /// How the file is compressed if it is uploaded
+    pub compression: Compression,
+    /// Account to log in with, `None` uses the server password
+    pub user: Option<String>,
     pub password: [u8; 32],
 }
 
@@ -28,41 +102,325 @@ impl Debug for Request {
             .field("action", &self.action)
             .field("path", &self.path)
             .field("hash", &self.hash)
+            .field("size", &self.size)
+            .field("compression", &self.compression)
+            .field("user", &self.user)
             .field("password", &"REDACTED")
             .finish()
     }
 }
 
-#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+/// Algorithm used to find out whether the server already has a file
+#[derive(
+    Debug,
+    Decode,
+    Encode,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Clone,
+    Copy,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// Fast, but not collision resistant against a malicious client
+    #[serde(rename = "xxhash")]
+    #[value(name = "xxhash")]
+    XxHash64,
+    Sha256,
+    Blake3,
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::XxHash64 => write!(f, "xxhash"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// Hash of the content of a file
+#[derive(
+    Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Digest {
+    #[serde(rename = "xxhash")]
+    XxHash64(u64),
+    Sha256([u8; 32]),
+    Blake3([u8; 32]),
+}
+
+impl Digest {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Digest::XxHash64(_) => HashAlgorithm::XxHash64,
+            Digest::Sha256(_) => HashAlgorithm::Sha256,
+            Digest::Blake3(_) => HashAlgorithm::Blake3,
+        }
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.algorithm())?;
+        match self {
+            Digest::XxHash64(hash) => write!(f, "{hash:016x}"),
+            Digest::Sha256(hash) | Digest::Blake3(hash) => {
+                hash.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd stream, the level and worker threads only matter to the sender
+    Zstd { level: i32, workers: u32 },
+}
+
+impl Compression {
+    pub fn is_enabled(&self) -> bool {
+        matches!(self, Compression::Zstd { .. })
+    }
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Action {
     Upload,
-    Run(bool),
+    /// Upload the file if needed and run it
+    Run(Program),
+    /// Run a file that is already on the server
+    Exec(Program),
+    Status,
+    /// Remove a file from the server, answered with an `ActionResult`
+    Delete,
+    /// List the deployed and the kept earlier versions of a file, answered with a
+    /// `FileHistory`
+    History,
+    /// Deploy an earlier version of a file again, the newest one or the one whose hash starts
+    /// with the given hex digits. Answered with a `RollbackResult`.
+    Rollback(Option<String>),
+    /// Remove the files that weren't run, uploaded or skipped for the given number of days,
+    /// least recently used first. Answered with a `GcResult`.
+    Gc(u32),
+}
+
+impl Action {
+    /// Whether the action operates on the file at `Request::path`
+    pub fn uses_file(&self) -> bool {
+        matches!(
+            self,
+            Action::Upload
+                | Action::Run(_)
+                | Action::Exec(_)
+                | Action::Delete
+                | Action::History
+                | Action::Rollback(_)
+        )
+    }
+
+    /// Whether the file at `Request::path` has to exist already
+    pub fn needs_file(&self) -> bool {
+        matches!(self, Action::Exec(_) | Action::Delete)
+    }
+
+    /// Whether the client sends the file if the hashes don't match
+    pub fn uploads(&self) -> bool {
+        matches!(self, Action::Upload | Action::Run(_))
+    }
+}
+
+/// Answer to an action that changes files on the server without uploading one
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum ActionResult {
+    Done,
+    Failed(String),
+}
+
+/// A version of a deployed file
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize)]
+pub struct FileVersion {
+    /// Hash of the content, with whichever accepted algorithm it was hashed with
+    #[serde(serialize_with = "serialize_display")]
+    pub hash: Digest,
+    pub size: u64,
+    /// When it was deployed, in seconds since the Unix epoch by the server's clock
+    pub deployed_at: u64,
+    /// Seconds since it was deployed, measured by the server, so the client's clock doesn't
+    /// matter
+    pub age_secs: u64,
+}
+
+/// Answer to `Action::History`
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Default, Serialize)]
+pub struct FileHistory {
+    /// The deployed file, `None` if it was deleted
+    pub current: Option<FileVersion>,
+    /// Earlier versions kept by the server, newest first
+    pub versions: Vec<FileVersion>,
+}
+
+/// Answer to `Action::Gc`
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum GcResult {
+    Collected(GcReport),
+    Failed(String),
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// Removed files, least recently used first
+    pub removed: Vec<RemovedFile>,
+    /// Bytes that became free, less than the size of the removed files if some were linked
+    /// elsewhere
+    pub freed: u64,
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize)]
+pub struct RemovedFile {
+    /// Relative to the root of the account, or to the server root for kept versions
+    pub path: PathBuf,
+    pub size: u64,
+    /// Seconds since the file was last used
+    pub unused_secs: u64,
+}
+
+/// Answer to `Action::Rollback`
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum RollbackResult {
+    /// The version that is deployed now
+    Restored(FileVersion),
+    Failed(String),
+}
+
+fn serialize_display<T: Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct Program {
+    /// Start the program using brickrun
+    pub brickrun: bool,
+    /// Arguments passed to the program
+    pub args: Vec<String>,
+}
+
+/// Messages sent by the server while a program is running
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum RunEvent {
+    Output(OutputStream, Vec<u8>),
+    Exit(ExitStatus),
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+pub struct ExitStatus {
+    pub code: Option<i32>,
+    /// Signal that terminated the program, if any
+    pub signal: Option<i32>,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Exit code following the shell convention of `128 + signal` for killed programs
+    pub fn exit_code(&self) -> i32 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        Self {
+            code: status.code(),
+            signal,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code: {code}"),
+            (None, Some(signal)) => write!(f, "signal: {signal}"),
+            (None, None) => write!(f, "unknown"),
+        }
+    }
 }
 
 #[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Validation {
-    pub password: MatchStatus,
+    pub password: AuthStatus,
     pub hash: MatchStatus,
     pub path: PathStatus,
+    pub space: SpaceStatus,
 }
 
 impl Default for Validation {
     fn default() -> Self {
         Self {
-            password: MatchStatus::Mismatch,
+            password: AuthStatus::Mismatch,
             hash: MatchStatus::Mismatch,
             path: PathStatus::Valid,
+            space: SpaceStatus::Available,
         }
     }
 }
 
-#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MatchStatus {
     Match,
     Mismatch,
 }
 
-#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, thiserror::Error)]
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthStatus {
+    Match,
+    Mismatch,
+    /// Too many wrong passwords came from the client's address, the password wasn't checked
+    LockedOut {
+        retry_after_secs: u64,
+    },
+    /// The password is right, but the account isn't allowed to do what it asked for
+    Forbidden,
+}
+
+#[derive(
+    Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, thiserror::Error, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
 pub enum PathStatus {
     #[error("Path is valid. This isn't an error")]
     Valid,
@@ -74,4 +432,50 @@ pub enum PathStatus {
     EscapesWorkingDir,
     #[error("Failed to canonicalize path")]
     CanonicalizationFailed,
+    #[error("File does not exist on the server")]
+    NotFound,
+    #[error("Path is reserved for the server's own files")]
+    Reserved,
+}
+
+/// Whether the server has room for an upload, only checked if the file is uploaded. Uploads
+/// without a size are refused.
+#[derive(
+    Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, thiserror::Error, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SpaceStatus {
+    #[error("There is enough space. This isn't an error")]
+    Available,
+    #[error("{needed} bytes needed, but only {available} bytes are left of the quota")]
+    QuotaExceeded { needed: u64, available: u64 },
+    #[error("{needed} bytes needed, but only {available} bytes are free on the disk")]
+    DiskFull { needed: u64, available: u64 },
+    #[error("The request doesn't say how large the file is")]
+    SizeUnknown,
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Default, Serialize)]
+pub struct RobotStatus {
+    pub power_supplies: Vec<PowerSupply>,
+    pub motors: Vec<Device>,
+    pub sensors: Vec<Device>,
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize)]
+pub struct PowerSupply {
+    pub name: String,
+    /// Voltage in microvolts
+    pub voltage: Option<u64>,
+    /// Current in microamperes
+    pub current: Option<u64>,
+    pub technology: Option<String>,
+}
+
+#[derive(Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize)]
+pub struct Device {
+    /// Port the device is connected to (e.g. `ev3-ports:outA`)
+    pub address: String,
+    pub driver_name: String,
+    pub mode: Option<String>,
 }
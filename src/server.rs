@@ -1,33 +1,123 @@
+mod access;
+mod accounts;
+mod audit;
+mod daemon;
+mod delete;
+mod discovery;
 mod download;
+mod ev3server;
+mod failed_logins;
+mod gc;
 mod handler;
 mod hash;
+mod hash_index;
+mod history;
 mod run;
+mod service;
+mod status;
+mod storage;
+mod update;
 mod validation;
 mod version;
+mod versions;
 
-use crate::cli::Server;
-use crate::hash::Hasher;
+use crate::cli::{Server, ServerCommand, seconds};
 use handler::ClientHandler;
 use std::{
+    env,
     io::{self},
-    net::TcpListener,
+    net::{SocketAddr, TcpListener},
+    time::Duration,
 };
-use tracing::{info, warn};
+use tracing::warn;
+
+pub use access::{Cidr, CidrError};
+pub use accounts::{Account, Permission, load_accounts};
+pub use ev3server::{Ev3Server, Ev3ServerBuilder, HookEvent, ShutdownHandle};
 
 pub fn server(config: Server) -> io::Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", config.server_port))?;
-    info!("Server listening on port {}", config.server_port);
+    if let Some(ServerCommand::InstallService(args)) = &config.command {
+        return service::install_service(&config, args);
+    }
+    if config.daemon {
+        return daemon::daemonize(config.log_file.as_deref(), config.pidfile.as_deref());
+    }
+
+    // Found before an update replaces the file
+    let executable = env::current_exe()?;
+    let listener = listen(&config).map_err(|e| update::roll_back(&executable, None, e))?;
+    let pidfile = config.pidfile.clone();
+    let server = listener
+        .try_clone()
+        .and_then(|clone| build(config, clone))
+        .map_err(|e| update::roll_back(&executable, Some(&listener), e))?;
+
+    let shutdown = server.shutdown_handle();
+    daemon::terminate_on_signals(shutdown.clone())?;
+    let _pidfile = pidfile
+        .as_deref()
+        .map(daemon::Pidfile::create)
+        .transpose()?;
+    server.run()?;
+
+    if shutdown.restart_requested() {
+        return Err(update::restart(&executable, &listener));
+    }
+    Ok(())
+}
 
-    let password_hash = Hasher::hash_password(&config.password);
-    info!("Password hash calculated");
+/// The socket passed by systemd, or a new one bound to the configured address
+fn listen(config: &Server) -> io::Result<TcpListener> {
+    match service::systemd_listener()? {
+        Some(listener) => Ok(listener),
+        None => TcpListener::bind(SocketAddr::new(config.bind, config.server_port))
+            .inspect_err(|e| warn!("Failed to listen on port {}: {e}", config.server_port)),
+    }
+}
 
-    loop {
-        let (socket, addr) = listener.accept()?;
-        info!("Accepted connection from {addr}");
+/// Server configured by the command line, accepting connections on `listener`
+fn build(config: Server, listener: TcpListener) -> io::Result<Ev3Server> {
+    let mut builder = Ev3Server::builder()
+        .listener(listener)
+        .allow(config.allow)
+        .deny(config.deny)
+        .password(config.password)
+        .root(config.root)
+        .sysfs_root(config.sysfs_root)
+        .hash_algorithms(config.hash_algorithms)
+        .max_message_size(config.max_message_size)
+        .max_chunk_size(config.max_chunk_size)
+        .max_window_log(config.max_window_log)
+        .handshake_timeout(seconds(config.handshake_timeout))
+        .idle_timeout(seconds(config.idle_timeout))
+        .keepalive(seconds(config.keepalive))
+        .max_login_failures(config.max_login_failures)
+        .login_backoff(Duration::from_secs(config.login_backoff))
+        .lockout(Duration::from_secs(config.lockout))
+        .audit_log_max_size(config.audit_log_max_size)
+        .audit_log_keep(config.audit_log_keep)
+        .keep_versions(config.keep_versions)
+        .allow_update(!config.no_update);
+
+    if let Some(file) = config.audit_log {
+        builder = builder.audit_log(file);
+    }
+    if let Some(bytes) = config.quota {
+        builder = builder.quota(bytes);
+    }
+    if let Some(days) = config.auto_gc {
+        builder = builder.auto_gc(Duration::from_secs(u64::from(days) * 24 * 60 * 60));
+    }
+    if let Some(path) = &config.accounts {
+        builder = builder.accounts(load_accounts(path)?);
+    }
 
-        let mut client_handler = ClientHandler::new(socket, password_hash);
-        if let Err(e) = client_handler.handle_client() {
-            warn!("Error while handling connection: {e}");
+    if !config.no_discovery {
+        builder = builder.discovery(config.discovery_port);
+        if let Some(name) = config.name {
+            builder = builder.name(name);
         }
     }
+
+    builder.build()
 }
@@ -0,0 +1,151 @@
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// Block of addresses in CIDR notation, like `192.168.0.0/24`, or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CidrError {
+    #[error("Invalid address: {0}")]
+    Address(String),
+    #[error("Invalid prefix length: {0}")]
+    Prefix(String),
+}
+
+impl Cidr {
+    /// Block of the addresses sharing the first `prefix` bits with `addr`
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, CidrError> {
+        let addr = addr.to_canonical();
+        if prefix > max_prefix(addr) {
+            return Err(CidrError::Prefix(prefix.to_string()));
+        }
+
+        let addr = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from_bits(
+                ip.to_bits() & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(
+                ip.to_bits() & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0),
+            )),
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+        Self::new(ip.to_canonical(), self.prefix).is_ok_and(|block| block.addr == self.addr)
+    }
+}
+
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| CidrError::Address(addr.to_owned()))?
+            .to_canonical();
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| CidrError::Prefix(prefix.to_owned()))?,
+            None => max_prefix(addr),
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Which client addresses may connect
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct AccessList {
+    /// Only these may connect, everyone if empty
+    pub(super) allow: Vec<Cidr>,
+    /// These may never connect, even if they are allowed
+    pub(super) deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    pub(super) fn permits(&self, ip: IpAddr) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip));
+        allowed && !self.deny.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(
+            "192.168.1.7/24".parse::<Cidr>().unwrap().to_string(),
+            "192.168.1.0/24"
+        );
+        assert_eq!(
+            "10.0.0.1".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.1/32"
+        );
+        assert_eq!(
+            "fe80::1/10".parse::<Cidr>().unwrap().to_string(),
+            "fe80::/10"
+        );
+        assert_eq!(
+            "0.0.0.0/0".parse::<Cidr>().unwrap().to_string(),
+            "0.0.0.0/0"
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(cidr.contains(ip("192.168.42.1")));
+        assert!(cidr.contains(ip("::ffff:192.168.42.1")));
+        assert!(!cidr.contains(ip("192.169.0.1")));
+        assert!(!cidr.contains(ip("fe80::1")));
+        assert!("::/0".parse::<Cidr>().unwrap().contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_deny_wins_over_allow() {
+        let access = AccessList {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.13.0/24".parse().unwrap()],
+        };
+        assert!(access.permits(ip("10.1.2.3")));
+        assert!(!access.permits(ip("10.0.13.37")));
+        assert!(!access.permits(ip("192.168.0.2")));
+        assert!(AccessList::default().permits(ip("192.168.0.2")));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{hash::Hasher, protocol::Action, server::hash_index::STATE_DIR};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+/// What an account may do besides querying the robot status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Upload,
+    /// Upload a file if needed and run it
+    Run,
+    Delete,
+    /// Run a file that is already on the server
+    Exec,
+    /// Replace the server binary with `client update-server`
+    Update,
+}
+
+impl Permission {
+    /// Permission needed for `action`, `None` if everyone may do it
+    pub(super) fn required(action: &Action) -> Option<Self> {
+        match action {
+            Action::Upload => Some(Permission::Upload),
+            Action::Run(_) => Some(Permission::Run),
+            Action::Exec(_) => Some(Permission::Exec),
+            Action::Delete | Action::Gc(_) => Some(Permission::Delete),
+            // Deploys a file like an upload
+            Action::Rollback(_) => Some(Permission::Upload),
+            Action::Status | Action::History => None,
+        }
+    }
+}
+
+/// Named login with its own password, directory and permissions.
+///
+/// ```
+/// use ev3_runner::{Account, Permission};
+///
+/// let student = Account::new("alice", "secret")
+///     .root("students/alice")
+///     .allow([Permission::Upload, Permission::Run]);
+/// ```
+#[derive(Clone)]
+pub struct Account {
+    pub(super) name: String,
+    pub(super) password: [u8; 32],
+    /// Relative to the server root until the server is built, then canonical
+    pub(super) root: PathBuf,
+    pub(super) permissions: Vec<Permission>,
+}
+
+impl Account {
+    /// Account without any permissions that works in the whole server root
+    pub fn new(name: impl Into<String>, password: &str) -> Self {
+        Self {
+            name: name.into(),
+            password: Hasher::hash_password(password),
+            root: PathBuf::new(),
+            permissions: Vec::new(),
+        }
+    }
+
+    /// Directory under the server root the account's paths are relative to, created if missing
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    pub fn allow(mut self, permissions: impl IntoIterator<Item = Permission>) -> Self {
+        self.permissions.extend(permissions);
+        self
+    }
+
+    pub(super) fn may(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    /// Creates the account's directory under the canonical server `root` and makes `root`
+    /// absolute
+    fn resolve(mut self, root: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Root of account {}: {message}", self.name),
+            )
+        };
+        if self.root.is_absolute() {
+            return Err(invalid("has to be relative to the server root"));
+        }
+
+        let dir = root.join(&self.root);
+        fs::create_dir_all(&dir)?;
+        let dir = dir.canonicalize()?;
+        if !dir.starts_with(root) {
+            return Err(invalid("is outside of the server root"));
+        }
+        if dir.starts_with(root.join(STATE_DIR)) {
+            return Err(invalid("is reserved for the server's own files"));
+        }
+
+        debug!(
+            "Account {} works in {} and may {:?}",
+            self.name,
+            dir.display(),
+            self.permissions
+        );
+        self.root = dir;
+        Ok(self)
+    }
+}
+
+impl Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("name", &self.name)
+            .field("password", &"REDACTED")
+            .field("root", &self.root)
+            .field("permissions", &self.permissions)
+            .finish()
+    }
+}
+
+/// Accounts by name, with their directories created under the canonical server `root`
+pub(super) fn resolve(accounts: Vec<Account>, root: &Path) -> io::Result<HashMap<String, Account>> {
+    let mut resolved = HashMap::new();
+    for account in accounts {
+        let account = account.resolve(root)?;
+        if let Some(previous) = resolved.insert(account.name.clone(), account) {
+            warn!(
+                "Account {} is defined twice, using the last one",
+                previous.name
+            );
+        }
+    }
+    Ok(resolved)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountsFile {
+    #[serde(default)]
+    account: Vec<AccountEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountEntry {
+    name: String,
+    password: String,
+    #[serde(default)]
+    root: PathBuf,
+    actions: Vec<Permission>,
+}
+
+/// Reads the `[[account]]` tables of a TOML file
+pub fn load_accounts(path: &Path) -> io::Result<Vec<Account>> {
+    let content = fs::read_to_string(path)
+        .inspect_err(|e| warn!("Failed to read accounts file {}: {e}", path.display()))?;
+    parse_accounts(&content).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid accounts file {}: {e}", path.display()),
+        )
+    })
+}
+
+fn parse_accounts(content: &str) -> Result<Vec<Account>, toml::de::Error> {
+    let file: AccountsFile = toml::from_str(content)?;
+    Ok(file
+        .account
+        .into_iter()
+        .map(|entry| {
+            Account::new(entry.name, &entry.password)
+                .root(entry.root)
+                .allow(entry.actions)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Program;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse_accounts() {
+        let accounts = parse_accounts(
+            r#"
+            [[account]]
+            name = "mentor"
+            password = "secret"
+            actions = ["upload", "run", "delete", "exec"]
+
+            [[account]]
+            name = "alice"
+            password = "hunter2"
+            root = "students/alice"
+            actions = ["upload", "run"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        let alice = &accounts[1];
+        assert_eq!(alice.name, "alice");
+        assert_eq!(alice.password, Hasher::hash_password("hunter2"));
+        assert_eq!(alice.root, Path::new("students/alice"));
+        assert!(alice.may(Permission::Run));
+        assert!(!alice.may(Permission::Delete));
+        assert!(!alice.may(Permission::Update));
+        assert_eq!(
+            Permission::required(&Action::Exec(Program::default())),
+            Some(Permission::Exec)
+        );
+        assert_eq!(Permission::required(&Action::Status), None);
+
+        assert!(parse_accounts("[[account]]\nname = \"bob\"\npassword = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_account_root_stays_inside_server_root() {
+        let tmp = TempDir::new("accounts");
+        let root = tmp.path();
+
+        let account = Account::new("alice", "x").root("students/alice");
+        let account = account.resolve(root).unwrap();
+        assert_eq!(account.root, root.join("students/alice"));
+        assert!(account.root.is_dir());
+
+        assert!(Account::new("eve", "x").root("..").resolve(root).is_err());
+        assert!(
+            Account::new("eve", "x")
+                .root(STATE_DIR)
+                .resolve(root)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    protocol::{
+        Action, AuthStatus, Digest, ExitStatus, PathStatus, Request, UpdateRequest, Validation,
+    },
+    server::handler::{ClientHandler, HandlerError},
+};
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// One line of the audit log, describing a connection
+#[derive(Debug, Serialize)]
+pub(super) struct AuditRecord {
+    /// When the connection was accepted, RFC 3339 in UTC
+    timestamp: String,
+    peer: SocketAddr,
+    user: Option<String>,
+    action: Option<&'static str>,
+    path: Option<PathBuf>,
+    /// Hash of the received file, or the one the client sent if nothing was received
+    pub(super) hash: Option<String>,
+    /// Size of the received file
+    pub(super) bytes: u64,
+    /// Whether the upload was skipped because the server already had the file, only set for
+    /// uploads
+    pub(super) skipped: Option<bool>,
+    auth: Option<AuthStatus>,
+    path_status: Option<PathStatus>,
+    pub(super) exit_status: Option<ExitStatus>,
+    duration_ms: u64,
+    error: Option<String>,
+}
+
+impl AuditRecord {
+    pub(super) fn new(peer: SocketAddr) -> Self {
+        Self {
+            timestamp: timestamp(SystemTime::now()),
+            peer,
+            user: None,
+            action: None,
+            path: None,
+            hash: None,
+            bytes: 0,
+            skipped: None,
+            auth: None,
+            path_status: None,
+            exit_status: None,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    /// Record of a connection that was closed before anything was read from it
+    pub(super) fn refused(peer: SocketAddr, error: &str) -> Self {
+        Self {
+            error: Some(error.to_owned()),
+            ..Self::new(peer)
+        }
+    }
+
+    pub(super) fn request(&mut self, request: &Request) {
+        self.user = request.user.clone();
+        self.action = Some(action_name(&request.action));
+        self.path = request.action.uses_file().then(|| request.path.clone());
+        self.hash = request.hash.map(|hash| hash.to_string());
+    }
+
+    pub(super) fn update(&mut self, request: &UpdateRequest) {
+        self.user = request.user.clone();
+        self.action = Some("update_server");
+        self.hash = Some(Digest::Sha256(request.sha256).to_string());
+    }
+
+    pub(super) fn auth(&mut self, status: AuthStatus) {
+        self.auth = Some(status);
+    }
+
+    pub(super) fn validation(&mut self, validation: &Validation) {
+        self.auth = Some(validation.password);
+        // The path is only checked once the client logged in
+        self.path_status = (validation.password == AuthStatus::Match && self.path.is_some())
+            .then_some(validation.path);
+    }
+}
+
+/// Appends records as JSON lines, moving the file to `<file>.1`, `<file>.2`, ... when it
+/// would grow beyond `max_size`
+#[derive(Debug)]
+pub(super) struct AuditLog {
+    file: PathBuf,
+    max_size: u64,
+    /// Rotated files kept besides the current one
+    keep: u32,
+}
+
+impl AuditLog {
+    /// Opens the log once to fail early if it can't be written
+    pub(super) fn open(file: PathBuf, max_size: u64, keep: u32) -> io::Result<Self> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .inspect_err(|e| warn!("Failed to open audit log {}: {e}", file.display()))?;
+        Ok(Self {
+            file,
+            max_size,
+            keep,
+        })
+    }
+
+    pub(super) fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let size = fs::metadata(&self.file).map_or(0, |metadata| metadata.len());
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        // A single write, so a crash can't leave half a line behind on most file systems
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)?
+            .write_all(&line)
+    }
+
+    /// Appends the record, a failure is only logged
+    pub(super) fn write(&self, record: &AuditRecord) {
+        if let Err(e) = self.append(record) {
+            warn!("Failed to write the audit log {}: {e}", self.file.display());
+        }
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.file);
+        }
+
+        for n in (1..self.keep).rev() {
+            match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(&self.file, self.rotated(1))
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = OsString::from(self.file.as_os_str());
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+}
+
+impl ClientHandler {
+    /// Writes the audit record of the connection, if the server keeps an audit log
+    pub(super) fn write_audit(&mut self, started: Instant, result: &Result<(), HandlerError>) {
+        let Some(log) = &self.settings.audit_log else {
+            return;
+        };
+
+        self.audit.duration_ms = started.elapsed().as_millis() as u64;
+        self.audit.error = result.as_ref().err().map(ToString::to_string);
+        log.write(&self.audit);
+    }
+}
+
+fn action_name(action: &Action) -> &'static str {
+    match action {
+        Action::Upload => "upload",
+        Action::Run(_) => "run",
+        Action::Exec(_) => "exec",
+        Action::Status => "status",
+        Action::Delete => "delete",
+        Action::History => "history",
+        Action::Rollback(_) => "rollback",
+        Action::Gc(_) => "gc",
+    }
+}
+
+/// Formats the time as RFC 3339 in UTC with milliseconds, e.g. `2024-05-01T12:30:00.250Z`
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the given day since 1970-01-01, see
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(timestamp(time), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn test_log_rotates_by_size() {
+        let tmp = TempDir::new("audit");
+        let dir = tmp.path();
+        let file = dir.join("audit.jsonl");
+
+        let record = AuditRecord::new("127.0.0.1:1234".parse().unwrap());
+        let line_len = serde_json::to_vec(&record).unwrap().len() as u64 + 1;
+        let log = AuditLog::open(file.clone(), 2 * line_len, 2).unwrap();
+        for _ in 0..7 {
+            log.append(&record).unwrap();
+        }
+
+        assert_eq!(fs::metadata(&file).unwrap().len(), line_len);
+        assert_eq!(fs::metadata(log.rotated(1)).unwrap().len(), 2 * line_len);
+        assert_eq!(fs::metadata(log.rotated(2)).unwrap().len(), 2 * line_len);
+        assert!(!log.rotated(3).exists());
+    }
+}
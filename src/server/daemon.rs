@@ -0,0 +1,206 @@
+use crate::server::ShutdownHandle;
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+use tracing::{debug, info, warn};
+
+/// Pidfile of the running server, removed when it is dropped
+#[derive(Debug)]
+pub(super) struct Pidfile(PathBuf);
+
+impl Pidfile {
+    /// Writes the id of this process, fails if the file names a server that is still running
+    pub(super) fn create(path: &Path) -> io::Result<Self> {
+        if let Some(pid) = read_pid(path)
+            && pid != process::id()
+            && is_running(pid)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "The server is already running with pid {pid}, see {}",
+                    path.display()
+                ),
+            ));
+        }
+
+        fs::write(path, format!("{}\n", process::id()))
+            .inspect_err(|e| warn!("Failed to write pidfile {}: {e}", path.display()))?;
+        debug!("Wrote pidfile {}", path.display());
+        Ok(Self(path.to_owned()))
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            warn!("Failed to remove pidfile {}: {e}", self.0.display());
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Only known on Linux, elsewhere a stale pidfile is assumed
+fn is_running(pid: u32) -> bool {
+    cfg!(target_os = "linux") && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Starts this command again without `--daemon` in its own process group, and returns once
+/// the server runs
+#[cfg(unix)]
+pub(super) fn daemonize(log_file: Option<&Path>, pidfile: Option<&Path>) -> io::Result<()> {
+    use std::{
+        env,
+        fs::OpenOptions,
+        os::unix::process::CommandExt,
+        process::{Command, Stdio},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const START_TIMEOUT: Duration = Duration::from_secs(10);
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+    /// Without a pidfile, a server that is still running after this long counts as started
+    const GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+    let log = match log_file {
+        Some(path) => Stdio::from(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .inspect_err(|e| warn!("Failed to open log file {}: {e}", path.display()))?,
+        ),
+        None => Stdio::null(),
+    };
+
+    let mut child = Command::new(env::current_exe()?)
+        .args(daemon_args(env::args_os().skip(1)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        // Keeps signals for the terminal's foreground process group away from the daemon
+        .process_group(0)
+        .spawn()
+        .inspect_err(|e| warn!("Failed to start the daemon: {e}"))?;
+    let pid = child.id();
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let hint = log_file
+                .map(|path| format!(", see {}", path.display()))
+                .unwrap_or_default();
+            return Err(io::Error::other(format!(
+                "The server exited while starting ({status}){hint}"
+            )));
+        }
+
+        let running = match pidfile {
+            Some(path) => read_pid(path) == Some(pid),
+            None => started.elapsed() >= GRACE_PERIOD,
+        };
+        if running {
+            info!("Server running in the background with pid {pid}");
+            return Ok(());
+        }
+        if started.elapsed() >= START_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("The server with pid {pid} didn't start in time"),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Arguments of the daemon, the ones of this process without the options only meant for
+/// starting it
+fn daemon_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut daemon_args = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--daemon" || arg.to_string_lossy().starts_with("--log-file=") {
+            continue;
+        }
+        if arg == "--log-file" {
+            args.next();
+            continue;
+        }
+        daemon_args.push(arg);
+    }
+    daemon_args
+}
+
+#[cfg(not(unix))]
+pub(super) fn daemonize(_log_file: Option<&Path>, _pidfile: Option<&Path>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--daemon is only supported on Unix",
+    ))
+}
+
+/// Terminates the server and its running program on SIGTERM and SIGINT, and exits right away
+/// on a second one. Programs run in their own process group, so they don't get the signal
+/// themselves.
+#[cfg(unix)]
+pub(super) fn terminate_on_signals(handle: ShutdownHandle) -> io::Result<()> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+    use std::thread;
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || {
+            for (count, signal) in signals.forever().enumerate() {
+                if count > 0 {
+                    warn!("Received another signal, exiting without waiting");
+                    process::exit(1);
+                }
+                let name = if signal == SIGTERM {
+                    "SIGTERM"
+                } else {
+                    "SIGINT"
+                };
+                info!("Received {name}, shutting down");
+                handle.terminate();
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(super) fn terminate_on_signals(_handle: ShutdownHandle) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_args() {
+        let args = [
+            "server",
+            "--daemon",
+            "--log-file",
+            "/var/log/ev3-runner.log",
+            "--root",
+            "programs",
+            "--log-file=/tmp/log",
+        ];
+        assert_eq!(
+            daemon_args(args.into_iter().map(OsString::from)),
+            ["server", "--root", "programs"]
+        );
+    }
+}
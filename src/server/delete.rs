@@ -0,0 +1,32 @@
+use crate::{
+    protocol::ActionResult,
+    server::handler::{ClientHandler, HandlerError},
+};
+use std::{fs, path::Path};
+use tracing::{debug, warn};
+
+impl ClientHandler {
+    /// Removes the file and tells the client whether it worked
+    pub(super) fn delete(&mut self, path: &Path) -> Result<(), HandlerError> {
+        debug!("Deleting {}", path.display());
+        self.keep_version(path);
+
+        let result = match fs::remove_file(path) {
+            Ok(()) => {
+                self.settings
+                    .hash_index
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(path);
+                ActionResult::Done
+            }
+            Err(e) => {
+                warn!("Failed to delete {}: {e}", path.display());
+                ActionResult::Failed(e.to_string())
+            }
+        };
+
+        self.transport.encode_and_write(result)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    VERSION,
+    protocol::{DISCOVERY_MAGIC, DiscoveryResponse},
+    server::access::AccessList,
+};
+use bincode::config::standard;
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Starts a thread answering discovery broadcasts from addresses `access` permits with the
+/// server's name, port and version until `stopped` is set.
+///
+/// Broadcasts only reach a socket bound to the unspecified address, so if the server is bound
+/// to an address, requests are only answered if they arrived on the interface that has it.
+pub(super) fn spawn_responder(
+    bind: IpAddr,
+    discovery_port: u16,
+    name: Option<String>,
+    tcp_port: u16,
+    access: AccessList,
+    stopped: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, discovery_port))?;
+    socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+    let interface = if bind.is_unspecified() {
+        None
+    } else {
+        interface_of(&socket, bind)?
+    };
+    info!("Discovery responder listening on udp port {discovery_port}");
+
+    let hostname = hostname();
+    let response = DiscoveryResponse {
+        name: name.unwrap_or_else(|| hostname.clone()),
+        hostname,
+        port: tcp_port,
+        version: VERSION.to_owned(),
+    };
+    let encoded = bincode::encode_to_vec(&response, standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    thread::Builder::new()
+        .name("discovery".to_owned())
+        .spawn(move || {
+            let mut buf = [0u8; 64];
+            while !stopped.load(Ordering::SeqCst) {
+                let (n, peer, received_on) = match recv_from(&socket, &mut buf) {
+                    Ok(received) => received,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Failed to receive discovery request: {e}");
+                        continue;
+                    }
+                };
+
+                if interface.is_some() && received_on != interface {
+                    debug!("Ignoring discovery request from {peer} on another interface");
+                    continue;
+                }
+                if !access.permits(peer.ip()) {
+                    debug!("Ignoring discovery request from {peer}, the address isn't allowed");
+                    continue;
+                }
+                if &buf[..n] != DISCOVERY_MAGIC {
+                    debug!("Ignoring unknown datagram from {peer}");
+                    continue;
+                }
+
+                debug!("Answering discovery request from {peer}");
+                if let Err(e) = socket.send_to(&encoded, peer) {
+                    warn!("Failed to answer discovery request from {peer}: {e}");
+                }
+            }
+        })
+}
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "ev3dev".to_owned())
+}
+
+/// Index of the interface that has `addr`, and makes `recv_from` report the interface
+/// datagrams arrive on
+#[cfg(target_os = "linux")]
+fn interface_of(socket: &UdpSocket, addr: IpAddr) -> io::Result<Option<u32>> {
+    use std::{mem, os::fd::AsRawFd, ptr};
+
+    let on: libc::c_int = 1;
+    // SAFETY: `on` outlives the call and its size is passed along
+    let set = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            ptr::from_ref(&on).cast(),
+            mem::size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    if set != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = ptr::null_mut();
+    // SAFETY: the list is only read until it is freed below
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut index = 0;
+    let mut entry = addrs;
+    while !entry.is_null() && index == 0 {
+        // SAFETY: `entry` is an element of the list getifaddrs returned
+        let ifaddrs = unsafe { &*entry };
+        // SAFETY: a non-null `ifa_addr` points to a socket address of its family
+        if !ifaddrs.ifa_addr.is_null() && unsafe { ip_of(ifaddrs.ifa_addr) } == Some(addr) {
+            // SAFETY: `ifa_name` is a C string owned by the list
+            index = unsafe { libc::if_nametoindex(ifaddrs.ifa_name) };
+        }
+        entry = ifaddrs.ifa_next;
+    }
+    // SAFETY: `addrs` came from getifaddrs and isn't used afterwards
+    unsafe { libc::freeifaddrs(addrs) };
+
+    if index == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No network interface has the address {addr}"),
+        ));
+    }
+    debug!("Answering discovery requests on interface {index} only");
+    Ok(Some(index))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_of(_socket: &UdpSocket, addr: IpAddr) -> io::Result<Option<u32>> {
+    warn!(
+        "Can't tell which interface discovery requests arrive on, answering them on all, not only on {addr}"
+    );
+    Ok(None)
+}
+
+/// # Safety
+///
+/// `addr` has to point to a socket address of the family it names
+#[cfg(target_os = "linux")]
+unsafe fn ip_of(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    // SAFETY: every socket address starts with its family, the caller guarantees the rest
+    unsafe {
+        match i32::from((*addr).sa_family) {
+            libc::AF_INET => {
+                let addr = &*addr.cast::<libc::sockaddr_in>();
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    addr.sin_addr.s_addr,
+                ))))
+            }
+            libc::AF_INET6 => {
+                let addr = &*addr.cast::<libc::sockaddr_in6>();
+                Some(IpAddr::from(addr.sin6_addr.s6_addr))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Like `UdpSocket::recv_from`, also returning the index of the interface the datagram
+/// arrived on if `interface_of` was called for the socket
+#[cfg(target_os = "linux")]
+fn recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    use std::{mem, os::fd::AsRawFd, ptr};
+
+    // SAFETY: all of these are plain C structs, for which zeroes are valid
+    let mut peer: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // u64s to align the control messages, large enough for an in_pktinfo
+    let mut control = [0u64; 8];
+    msg.msg_name = ptr::from_mut(&mut peer).cast();
+    msg.msg_namelen = mem::size_of_val(&peer) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: `msg` points to buffers that outlive the call, with their sizes
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut interface = None;
+    // SAFETY: recvmsg filled in the control messages within `msg_controllen`
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        // SAFETY: `cmsg` is a control message recvmsg filled in
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_PKTINFO {
+            // SAFETY: an IP_PKTINFO message carries an in_pktinfo
+            let info =
+                unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::in_pktinfo>()) };
+            interface = u32::try_from(info.ipi_ifindex).ok();
+        }
+        // SAFETY: as above
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    let ip = Ipv4Addr::from(u32::from_be(peer.sin_addr.s_addr));
+    let peer = SocketAddr::from((ip, u16::from_be(peer.sin_port)));
+    Ok((n as usize, peer, interface))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn recv_from(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<u32>)> {
+    let (n, peer) = socket.recv_from(buf)?;
+    Ok((n, peer, None))
+}
@@ -1,35 +1,84 @@
 use crate::{
+    hash::HashWriter,
+    protocol::{Compression, Digest, HashAlgorithm},
     server::handler::{ClientHandler, HandlerError},
     transport::Transport,
 };
-use std::{fs::OpenOptions, io::BufWriter, path::Path};
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
 use tracing::{debug, warn};
 
 impl ClientHandler {
+    /// Receives the file of `size` bytes and returns its hash and size. The file is received
+    /// next to `path` and only replaces it once it arrived completely with the `expected` hash,
+    /// so a failed upload leaves the deployed file alone.
     pub(super) fn download(
         &mut self,
         path: &Path,
-        use_compression: bool,
-    ) -> Result<(), HandlerError> {
+        compression: Compression,
+        hash_algorithm: HashAlgorithm,
+        size: u64,
+        expected: Option<Digest>,
+    ) -> Result<(Digest, u64), HandlerError> {
         debug!("Downloading file to {:?}", path.display());
 
+        let partial = partial_path(path);
+        let received = self.receive(&partial, compression, hash_algorithm, size, expected);
+        let result = received.and_then(|received| {
+            // A new inode instead of overwriting the old one, which a kept version may share
+            fs::rename(&partial, path)?;
+            Ok(received)
+        });
+        if result.is_err()
+            && let Err(e) = fs::remove_file(&partial)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove the partial file: {e}");
+        }
+        result
+    }
+
+    fn receive(
+        &mut self,
+        partial: &Path,
+        compression: Compression,
+        hash_algorithm: HashAlgorithm,
+        size: u64,
+        expected: Option<Digest>,
+    ) -> Result<(Digest, u64), HandlerError> {
         let file = OpenOptions::new()
             .create(true)
             .write(true)
-            .read(true)
             .truncate(true)
-            .open(path)
-            .inspect_err(|e| {
-                warn!(
-                    "Failed to open file with create, write and truncate options set to true: {e}"
-                )
-            })?;
+            .open(partial)
+            .inspect_err(|e| warn!("Failed to create {}: {e}", partial.display()))?;
 
-        let mut writer = BufWriter::with_capacity(Transport::FILE_TRANSFER_BUFFER, file);
+        let mut writer = HashWriter::new(
+            BufWriter::with_capacity(Transport::FILE_TRANSFER_BUFFER, file),
+            hash_algorithm,
+        );
+        let bytes = self
+            .transport
+            .download_file(&mut writer, compression, size)?;
+        let hash = writer.finish();
 
-        self.transport.download_file(&mut writer, use_compression)?;
-
-        Ok(())
+        if bytes != size {
+            return Err(HandlerError::ReceivedFile(format!(
+                "Received {bytes} bytes instead of {size}"
+            )));
+        }
+        if let Some(expected) = expected
+            && hash != expected
+        {
+            return Err(HandlerError::ReceivedFile(format!(
+                "Received {hash} instead of {expected}"
+            )));
+        }
+        Ok((hash, bytes))
     }
 
     #[cfg(unix)]
@@ -46,3 +95,11 @@ impl ClientHandler {
         Ok(())
     }
 }
+
+/// Hidden file next to `path` a new version is received into
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".partial");
+    path.with_file_name(name)
+}
@@ -0,0 +1,669 @@
+use crate::{
+    cli::{
+        DEFAULT_AUDIT_LOG_KEEP, DEFAULT_AUDIT_LOG_MAX_SIZE, DEFAULT_DISCOVERY_PORT,
+        DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_IDLE_TIMEOUT, DEFAULT_KEEP_VERSIONS, DEFAULT_KEEPALIVE,
+        DEFAULT_PASSWORD,
+    },
+    hash::Hasher,
+    protocol::{ExitStatus, HashAlgorithm, Request},
+    server::{
+        access::{AccessList, Cidr},
+        accounts::{self, Account},
+        audit::{AuditLog, AuditRecord},
+        discovery,
+        failed_logins::{FailedLogins, LoginLimits},
+        handler::ClientHandler,
+        hash_index::HashIndex,
+        storage::Storage,
+        versions::Versions,
+    },
+    transport::{CHUNK_SIZE, Limits, MAX_MESSAGE_SIZE_LIMIT, WINDOW_LOG_RANGE},
+};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::Child,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Server that receives and runs programs from ev3-runner clients.
+///
+/// Connections are handled one after another on the thread calling `run`.
+///
+/// ```no_run
+/// use ev3_runner::Ev3Server;
+/// use std::thread;
+///
+/// let server = Ev3Server::builder()
+///     .bind("0.0.0.0:6767".parse().unwrap())
+///     .password("mysecret")
+///     .root("/home/robot/programs")
+///     .after_run(|event| println!("{} exited with {:?}", event.path.display(), event.exit_status))
+///     .build()?;
+///
+/// let shutdown = server.shutdown_handle();
+/// let thread = thread::spawn(move || server.run());
+/// // ...
+/// shutdown.shutdown();
+/// thread.join().unwrap()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Ev3Server {
+    listener: TcpListener,
+    access: AccessList,
+    settings: Arc<Settings>,
+    discovery: Option<(u16, Option<String>)>,
+    shutdown: ShutdownHandle,
+}
+
+pub struct Ev3ServerBuilder {
+    bind: SocketAddr,
+    listener: Option<TcpListener>,
+    access: AccessList,
+    password: String,
+    root: PathBuf,
+    sysfs_root: PathBuf,
+    discovery_port: Option<u16>,
+    name: Option<String>,
+    hash_algorithms: Vec<HashAlgorithm>,
+    limits: Limits,
+    timeouts: Timeouts,
+    login_limits: LoginLimits,
+    accounts: Vec<Account>,
+    audit_log: Option<PathBuf>,
+    audit_log_max_size: u64,
+    audit_log_keep: u32,
+    keep_versions: usize,
+    quota: Option<u64>,
+    auto_gc: Option<Duration>,
+    allow_update: bool,
+    hooks: Hooks,
+}
+
+/// What a hook is called for
+#[derive(Debug)]
+pub struct HookEvent<'a> {
+    pub request: &'a Request,
+    /// Path of the file on the server
+    pub path: &'a Path,
+    pub peer: SocketAddr,
+    /// Exit status of the program, only set for `after_run`
+    pub exit_status: Option<ExitStatus>,
+}
+
+pub(super) type Hook = Box<dyn Fn(&HookEvent) + Send + Sync>;
+
+#[derive(Default)]
+pub(super) struct Hooks {
+    pub(super) before_upload: Vec<Hook>,
+    pub(super) after_upload: Vec<Hook>,
+    pub(super) before_run: Vec<Hook>,
+    pub(super) after_run: Vec<Hook>,
+}
+
+/// Settings shared by all connections
+pub(super) struct Settings {
+    pub(super) password: [u8; 32],
+    /// Canonical directory all client paths are relative to
+    pub(super) root: PathBuf,
+    pub(super) sysfs_root: PathBuf,
+    pub(super) hooks: Hooks,
+    /// Accepted hash algorithms, most preferred first, never empty
+    pub(super) hash_algorithms: Vec<HashAlgorithm>,
+    pub(super) hash_index: Mutex<HashIndex>,
+    pub(super) versions: Versions,
+    pub(super) storage: Storage,
+    /// Largest message and file chunk accepted from clients
+    pub(super) limits: Limits,
+    pub(super) timeouts: Timeouts,
+    pub(super) failed_logins: Mutex<FailedLogins>,
+    /// Accounts by name with canonical roots, the server password is used if there are none
+    pub(super) accounts: HashMap<String, Account>,
+    pub(super) audit_log: Option<AuditLog>,
+    /// Program started by the current connection, killed by `ShutdownHandle::terminate`
+    pub(super) program: RunningProgram,
+    pub(super) allow_update: bool,
+    /// Set once a new binary was installed, stops the server after the connection
+    pub(super) restart: Arc<AtomicBool>,
+}
+
+pub(super) type RunningProgram = Arc<Mutex<Option<Arc<Mutex<Child>>>>>;
+
+/// Timeouts of client connections, `None` waits forever
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Timeouts {
+    /// From accepting the connection until the version was checked, and from then until the
+    /// request was validated
+    pub(super) handshake: Option<Duration>,
+    /// For every read and write after the handshake, except waiting for a running program
+    pub(super) idle: Option<Duration>,
+    pub(super) keepalive: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake: Some(Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT)),
+            idle: Some(Duration::from_secs(DEFAULT_IDLE_TIMEOUT)),
+            keepalive: Some(Duration::from_secs(DEFAULT_KEEPALIVE)),
+        }
+    }
+}
+
+/// Stops a running `Ev3Server` from another thread
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    stopped: Arc<AtomicBool>,
+    addr: SocketAddr,
+    program: RunningProgram,
+    restart: Arc<AtomicBool>,
+}
+
+impl Default for Ev3ServerBuilder {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 6767),
+            listener: None,
+            access: AccessList::default(),
+            password: DEFAULT_PASSWORD.to_owned(),
+            root: PathBuf::from("."),
+            sysfs_root: PathBuf::from("/sys"),
+            discovery_port: None,
+            name: None,
+            hash_algorithms: DEFAULT_HASH_ALGORITHMS.to_vec(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            login_limits: LoginLimits::default(),
+            accounts: Vec::new(),
+            audit_log: None,
+            audit_log_max_size: DEFAULT_AUDIT_LOG_MAX_SIZE,
+            audit_log_keep: DEFAULT_AUDIT_LOG_KEEP,
+            keep_versions: DEFAULT_KEEP_VERSIONS,
+            quota: None,
+            auto_gc: None,
+            allow_update: false,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl Ev3ServerBuilder {
+    /// Address to listen on, port 0 picks a free port
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = addr;
+        self
+    }
+
+    /// Accept connections on a listener that is already bound instead of binding `bind`, e.g.
+    /// one passed in by systemd
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Only accept connections from these addresses. Everyone may connect if no address is
+    /// allowed.
+    pub fn allow(mut self, cidrs: impl IntoIterator<Item = Cidr>) -> Self {
+        self.access.allow.extend(cidrs);
+        self
+    }
+
+    /// Never accept connections from these addresses, even if they are allowed
+    pub fn deny(mut self, cidrs: impl IntoIterator<Item = Cidr>) -> Self {
+        self.access.deny.extend(cidrs);
+        self
+    }
+
+    /// Password clients have to send
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = password.into();
+        self
+    }
+
+    /// Directory uploaded files are stored in and programs are run from
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Root of the sysfs tree used for status queries
+    pub fn sysfs_root(mut self, sysfs_root: impl Into<PathBuf>) -> Self {
+        self.sysfs_root = sysfs_root.into();
+        self
+    }
+
+    /// Answer discovery broadcasts on the given UDP port
+    pub fn discovery(mut self, port: u16) -> Self {
+        self.discovery_port = Some(port);
+        self
+    }
+
+    /// Robot name announced to discovery requests, enables discovery on the default port
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self.discovery_port.get_or_insert(DEFAULT_DISCOVERY_PORT);
+        self
+    }
+
+    /// Hash algorithms clients may use to skip uploads, most preferred first.
+    ///
+    /// Clients that don't choose one themselves use the first. Files hashed with other
+    /// algorithms are always uploaded.
+    pub fn hash_algorithms(mut self, algorithms: impl IntoIterator<Item = HashAlgorithm>) -> Self {
+        self.hash_algorithms = algorithms.into_iter().collect();
+        self
+    }
+
+    /// Largest message in bytes accepted from clients, at most 16 MiB, defaults to 1 MiB
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.limits.max_message_size = size;
+        self
+    }
+
+    /// Largest chunk of an uploaded file in bytes, at least the 32 KiB clients send and at
+    /// most 16 MiB, defaults to 256 KiB
+    pub fn max_chunk_size(mut self, size: usize) -> Self {
+        self.limits.max_chunk_size = size;
+        self
+    }
+
+    /// Largest zstd window of a compressed upload as a power of two, 10 to 27. Defaults to 23
+    /// (8 MiB), which is enough for every compression level clients accept.
+    pub fn max_window_log(mut self, log: u32) -> Self {
+        self.limits.max_window_log = log;
+        self
+    }
+
+    /// Time a client has from connecting until its version is checked, and again from then
+    /// until its request is validated, so a client that connects and goes silent can't block
+    /// the server. Defaults to 20 seconds.
+    pub fn handshake_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.handshake = timeout.into();
+        self
+    }
+
+    /// Maximum time a read or write may block after the handshake, e.g. when an upload
+    /// stalls. Running programs may stay silent for as long as they like. Defaults to 60
+    /// seconds.
+    pub fn idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.idle = timeout.into();
+        self
+    }
+
+    /// Idle time after which TCP keepalive probes check that the client is still there, which
+    /// notices clients that vanished while a program runs. Defaults to 15 seconds.
+    pub fn keepalive(mut self, idle: impl Into<Option<Duration>>) -> Self {
+        self.timeouts.keepalive = idle.into();
+        self
+    }
+
+    /// Wrong passwords in a row until a client address is locked out, 0 disables the limit.
+    /// Defaults to 5.
+    pub fn max_login_failures(mut self, count: u32) -> Self {
+        self.login_limits.max_failures = count;
+        self
+    }
+
+    /// Time a client address has to wait after its first wrong password, doubled for every
+    /// further one. Defaults to 1 second.
+    pub fn login_backoff(mut self, backoff: Duration) -> Self {
+        self.login_limits.backoff = backoff;
+        self
+    }
+
+    /// How long a client address is locked out after too many wrong passwords. Defaults to
+    /// 5 minutes.
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.login_limits.lockout = lockout;
+        self
+    }
+
+    /// Lets a named account log in. Once there is an account, clients have to log in with
+    /// one and the server password isn't accepted anymore.
+    pub fn account(mut self, account: Account) -> Self {
+        self.accounts.push(account);
+        self
+    }
+
+    pub fn accounts(mut self, accounts: impl IntoIterator<Item = Account>) -> Self {
+        self.accounts.extend(accounts);
+        self
+    }
+
+    /// Append a JSON line describing every connection to this file
+    pub fn audit_log(mut self, file: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(file.into());
+        self
+    }
+
+    /// Size in bytes the audit log may grow to before it is rotated. Defaults to 1 MiB.
+    pub fn audit_log_max_size(mut self, size: u64) -> Self {
+        self.audit_log_max_size = size;
+        self
+    }
+
+    /// Rotated audit logs kept besides the current one, the oldest is deleted. Defaults to 3.
+    pub fn audit_log_keep(mut self, count: u32) -> Self {
+        self.audit_log_keep = count;
+        self
+    }
+
+    /// Earlier versions of every file kept when it is replaced or deleted, so clients can roll
+    /// back to them. 0 keeps none. Defaults to 3.
+    pub fn keep_versions(mut self, count: usize) -> Self {
+        self.keep_versions = count;
+        self
+    }
+
+    /// Bytes the files clients deployed and their kept versions may take up together.
+    /// Uploads that don't fit are refused. Defaults to no limit.
+    pub fn quota(mut self, bytes: u64) -> Self {
+        self.quota = Some(bytes);
+        self
+    }
+
+    /// When an upload doesn't fit into the quota or onto the disk, remove deployed files that
+    /// weren't used for this long, least recently used first, until it does. Defaults to never.
+    pub fn auto_gc(mut self, unused_for: Duration) -> Self {
+        self.auto_gc = Some(unused_for);
+        self
+    }
+
+    /// Let clients replace the executable of this process with `client update-server`, only
+    /// meant for the ev3-runner binary. `run` returns after an update and the caller has to
+    /// start the new binary, see `ShutdownHandle::restart_requested`. Defaults to false.
+    pub fn allow_update(mut self, allow: bool) -> Self {
+        self.allow_update = allow;
+        self
+    }
+
+    /// Called before a file is received, not if the server already has it
+    pub fn before_upload(mut self, hook: impl Fn(&HookEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.before_upload.push(Box::new(hook));
+        self
+    }
+
+    /// Called after a file was received
+    pub fn after_upload(mut self, hook: impl Fn(&HookEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.after_upload.push(Box::new(hook));
+        self
+    }
+
+    /// Called before a program is started
+    pub fn before_run(mut self, hook: impl Fn(&HookEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.before_run.push(Box::new(hook));
+        self
+    }
+
+    /// Called after a program exited, also if it was stopped by the client
+    pub fn after_run(mut self, hook: impl Fn(&HookEvent) + Send + Sync + 'static) -> Self {
+        self.hooks.after_run.push(Box::new(hook));
+        self
+    }
+
+    /// Binds the listener, fails if the address is taken, the root directory doesn't exist,
+    /// no hash algorithm is accepted, a size limit is out of range or the root of an account
+    /// is outside the root directory
+    pub fn build(self) -> io::Result<Ev3Server> {
+        if self.hash_algorithms.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "At least one hash algorithm has to be accepted",
+            ));
+        }
+        if self.limits.max_message_size > MAX_MESSAGE_SIZE_LIMIT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The maximum message size can't be more than {MAX_MESSAGE_SIZE_LIMIT} bytes"
+                ),
+            ));
+        }
+        if !(CHUNK_SIZE..=MAX_MESSAGE_SIZE_LIMIT).contains(&self.limits.max_chunk_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The maximum chunk size has to be between {CHUNK_SIZE} and {MAX_MESSAGE_SIZE_LIMIT} bytes"
+                ),
+            ));
+        }
+
+        if !WINDOW_LOG_RANGE.contains(&self.limits.max_window_log) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The maximum window log has to be between {} and {}",
+                    WINDOW_LOG_RANGE.start(),
+                    WINDOW_LOG_RANGE.end()
+                ),
+            ));
+        }
+
+        let root = self
+            .root
+            .canonicalize()
+            .inspect_err(|e| warn!("Failed to open root directory {}: {e}", self.root.display()))?;
+
+        let accounts = accounts::resolve(self.accounts, &root)?;
+        let audit_log = self
+            .audit_log
+            .map(|file| AuditLog::open(file, self.audit_log_max_size, self.audit_log_keep))
+            .transpose()?;
+        let hash_index = HashIndex::load(&root);
+        let versions = Versions::new(&root, self.keep_versions);
+        let storage = Storage::new(&root, self.quota, self.auto_gc);
+
+        let listener = match self.listener {
+            Some(listener) => listener,
+            None => TcpListener::bind(self.bind)?,
+        };
+        let addr = listener.local_addr()?;
+        let program = RunningProgram::default();
+        let restart = Arc::new(AtomicBool::new(false));
+
+        let password = Hasher::hash_password(&self.password);
+        debug!("Password hash calculated");
+
+        Ok(Ev3Server {
+            listener,
+            access: self.access,
+            settings: Arc::new(Settings {
+                password,
+                root,
+                sysfs_root: self.sysfs_root,
+                hooks: self.hooks,
+                hash_algorithms: self.hash_algorithms,
+                hash_index: Mutex::new(hash_index),
+                versions,
+                storage,
+                limits: self.limits,
+                timeouts: self.timeouts,
+                failed_logins: Mutex::new(FailedLogins::new(self.login_limits)),
+                accounts,
+                audit_log,
+                program: Arc::clone(&program),
+                allow_update: self.allow_update,
+                restart: Arc::clone(&restart),
+            }),
+            discovery: self.discovery_port.map(|port| (port, self.name)),
+            shutdown: ShutdownHandle {
+                stopped: Arc::new(AtomicBool::new(false)),
+                addr,
+                program,
+                restart,
+            },
+        })
+    }
+}
+
+impl Ev3Server {
+    pub fn builder() -> Ev3ServerBuilder {
+        Ev3ServerBuilder::default()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts and handles connections until `ShutdownHandle::shutdown` is called or a new
+    /// binary was installed
+    pub fn run(self) -> io::Result<()> {
+        let port = self.shutdown.addr.port();
+        info!("Server listening on {}", self.shutdown.addr);
+        info!("Serving files from {}", self.settings.root.display());
+
+        if let Some((discovery_port, name)) = self.discovery {
+            discovery::spawn_responder(
+                self.shutdown.addr.ip(),
+                discovery_port,
+                name,
+                port,
+                self.access.clone(),
+                Arc::clone(&self.shutdown.stopped),
+            )?;
+        }
+
+        loop {
+            let (socket, addr) = self.listener.accept()?;
+            if self.shutdown.is_shutdown() {
+                info!("Server shut down");
+                return Ok(());
+            }
+            if !self.access.permits(addr.ip()) {
+                warn!("Refused connection from {addr}, the address isn't allowed");
+                if let Some(log) = &self.settings.audit_log {
+                    log.write(&AuditRecord::refused(addr, "address not allowed"));
+                }
+                continue;
+            }
+            info!("Accepted connection from {addr}");
+
+            let mut client_handler = ClientHandler::new(socket, addr, Arc::clone(&self.settings));
+            if let Err(e) = client_handler.handle_client() {
+                warn!("Error while handling connection: {e}");
+            }
+            if self.shutdown.restart_requested() {
+                info!("Server stopped to restart with the new binary");
+                self.shutdown.stopped.store(true, Ordering::SeqCst);
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Stops the server once the current connection, if any, is done
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        // Wake up the blocking accept
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let addr = SocketAddr::new(ip, self.addr.port());
+        if let Err(e) = TcpStream::connect_timeout(&addr, WAKE_TIMEOUT) {
+            debug!("Failed to wake up the server: {e}");
+        }
+    }
+
+    /// Stops the server like `shutdown`, but kills the running program first, so the server
+    /// doesn't wait for it to exit
+    pub fn terminate(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let program = self
+            .program
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(child) = program {
+            info!("Stopping the running program");
+            let mut child = child.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = kill_process_group(&mut child) {
+                warn!("Failed to kill the running program: {e}");
+            }
+        }
+        self.shutdown();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Whether `run` returned because a client installed a new binary, which should be
+    /// started in place of this process
+    pub fn restart_requested(&self) -> bool {
+        self.restart.load(Ordering::SeqCst)
+    }
+}
+
+/// Kills the program and everything it started, which would otherwise keep its output open
+#[cfg(unix)]
+pub(super) fn kill_process_group(child: &mut Child) -> io::Result<()> {
+    // The id could already belong to another group once the program was reaped
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+
+    let group = i32::try_from(child.id()).map_err(io::Error::other)?;
+    // SAFETY: kill only sends a signal, the program runs in a group with its own id
+    if unsafe { libc::kill(-group, libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn kill_process_group(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+/// Hash algorithms accepted by default, the cryptographic ones first
+pub const DEFAULT_HASH_ALGORITHMS: [HashAlgorithm; 3] = [
+    HashAlgorithm::Blake3,
+    HashAlgorithm::Sha256,
+    HashAlgorithm::XxHash64,
+];
+
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl Debug for Ev3ServerBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ev3ServerBuilder")
+            .field("bind", &self.bind)
+            .field("listener", &self.listener)
+            .field("access", &self.access)
+            .field("password", &"REDACTED")
+            .field("root", &self.root)
+            .field("sysfs_root", &self.sysfs_root)
+            .field("discovery_port", &self.discovery_port)
+            .field("name", &self.name)
+            .field("hash_algorithms", &self.hash_algorithms)
+            .field("limits", &self.limits)
+            .field("timeouts", &self.timeouts)
+            .field("login_limits", &self.login_limits)
+            .field("accounts", &self.accounts)
+            .field("audit_log", &self.audit_log)
+            .field("audit_log_max_size", &self.audit_log_max_size)
+            .field("audit_log_keep", &self.audit_log_keep)
+            .field("keep_versions", &self.keep_versions)
+            .field("quota", &self.quota)
+            .field("auto_gc", &self.auto_gc)
+            .field("allow_update", &self.allow_update)
+            .finish_non_exhaustive()
+    }
+}
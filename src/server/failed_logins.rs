@@ -0,0 +1,155 @@
+use crate::cli::{DEFAULT_LOCKOUT, DEFAULT_LOGIN_BACKOFF, DEFAULT_MAX_LOGIN_FAILURES};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// When wrong passwords start locking out an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct LoginLimits {
+    /// Wrong passwords in a row until the address is locked out, 0 disables the limit
+    pub(super) max_failures: u32,
+    /// Wait after the first wrong password, doubled for every further one
+    pub(super) backoff: Duration,
+    pub(super) lockout: Duration,
+}
+
+impl Default for LoginLimits {
+    fn default() -> Self {
+        Self {
+            max_failures: DEFAULT_MAX_LOGIN_FAILURES,
+            backoff: Duration::from_secs(DEFAULT_LOGIN_BACKOFF),
+            lockout: Duration::from_secs(DEFAULT_LOCKOUT),
+        }
+    }
+}
+
+/// Wrong passwords per client address, so guessing the password takes forever
+#[derive(Debug)]
+pub(super) struct FailedLogins {
+    limits: LoginLimits,
+    addresses: HashMap<IpAddr, Failures>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    blocked_until: Instant,
+}
+
+impl FailedLogins {
+    pub(super) fn new(limits: LoginLimits) -> Self {
+        Self {
+            limits,
+            addresses: HashMap::new(),
+        }
+    }
+
+    /// How much longer `ip` has to wait before its password is checked again
+    pub(super) fn blocked(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let failures = self.addresses.get(&ip)?;
+        let remaining = failures.blocked_until.saturating_duration_since(now);
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Records a wrong password and returns how long `ip` is blocked now
+    pub(super) fn failure(&mut self, ip: IpAddr, now: Instant) -> Duration {
+        if self.limits.max_failures == 0 {
+            return Duration::ZERO;
+        }
+
+        // Failures are forgotten once the address behaved for as long as a lockout lasts
+        let lockout = self.limits.lockout;
+        self.addresses
+            .retain(|_, failures| now < failures.blocked_until + lockout);
+
+        let failures = self.addresses.entry(ip).or_insert(Failures {
+            count: 0,
+            blocked_until: now,
+        });
+        failures.count += 1;
+
+        let blocked = if failures.count >= self.limits.max_failures {
+            warn!(
+                "{ip} sent {} wrong passwords in a row, locked out for {lockout:?}",
+                failures.count
+            );
+            failures.count = 0;
+            lockout
+        } else {
+            let backoff = backoff(self.limits.backoff, failures.count).min(lockout);
+            warn!(
+                "Wrong password {} of {} from {ip}, next attempt allowed in {backoff:?}",
+                failures.count, self.limits.max_failures
+            );
+            backoff
+        };
+        failures.blocked_until = now + blocked;
+        blocked
+    }
+
+    pub(super) fn success(&mut self, ip: IpAddr) {
+        if self.addresses.remove(&ip).is_some() {
+            info!("{ip} sent the right password, forgetting its wrong ones");
+        }
+    }
+}
+
+/// `base` doubled for every failure after the first
+fn backoff(base: Duration, count: u32) -> Duration {
+    base.saturating_mul(1 << (count - 1).min(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 3));
+
+    fn logins() -> FailedLogins {
+        FailedLogins::new(LoginLimits {
+            max_failures: 3,
+            backoff: Duration::from_secs(1),
+            lockout: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn test_backoff_then_lockout() {
+        let mut logins = logins();
+        let now = Instant::now();
+
+        assert_eq!(logins.failure(IP, now), Duration::from_secs(1));
+        assert_eq!(logins.blocked(IP, now), Some(Duration::from_secs(1)));
+        assert_eq!(logins.blocked(OTHER, now), None);
+
+        let now = now + Duration::from_secs(1);
+        assert_eq!(logins.blocked(IP, now), None);
+        assert_eq!(logins.failure(IP, now), Duration::from_secs(2));
+
+        let now = now + Duration::from_secs(2);
+        assert_eq!(logins.failure(IP, now), Duration::from_secs(60));
+        assert_eq!(
+            logins.blocked(IP, now + Duration::from_secs(30)),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_success_and_time_forget_failures() {
+        let mut logins = logins();
+        let now = Instant::now();
+
+        logins.failure(IP, now);
+        logins.success(IP);
+        assert_eq!(logins.failure(IP, now), Duration::from_secs(1));
+
+        // Long after the last failure it counts as the first one again
+        let later = now + Duration::from_secs(120);
+        assert_eq!(logins.failure(IP, later), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    protocol::{GcReport, GcResult, RemovedFile, SpaceStatus},
+    server::{
+        handler::{ClientHandler, HandlerError},
+        storage,
+    },
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info, warn};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl ClientHandler {
+    /// Whether an upload of `size` bytes to `path` fits, after removing unused files if the
+    /// server collects them automatically
+    pub(super) fn check_space(&self, path: &Path, size: u64) -> Result<SpaceStatus, HandlerError> {
+        let storage = &self.settings.storage;
+        // The replaced file only becomes free if it isn't kept as an earlier version
+        let replaced = if self.settings.versions.is_enabled() {
+            0
+        } else {
+            storage::freed_by_removing(path)
+        };
+        let needed = size.saturating_sub(replaced);
+
+        let root = self.settings.root.clone();
+        let deployed = self.deployed(&root);
+        let versions = self.settings.versions.dir(&root)?;
+        let status = storage.check(needed, &deployed, &versions)?;
+        let (SpaceStatus::QuotaExceeded { available, .. }
+        | SpaceStatus::DiskFull { available, .. }) = status
+        else {
+            return Ok(status);
+        };
+        let Some(unused_for) = storage.auto_gc() else {
+            return Ok(status);
+        };
+
+        info!(
+            "Not enough space for {}, removing unused files",
+            path.display()
+        );
+        let report = self.collect(&root, unused_for, Some(needed - available), Some(path))?;
+        debug!("Removed {} files", report.removed.len());
+        Ok(storage.check(needed, &self.deployed(&root), &versions)?)
+    }
+
+    /// Removes the files of the client that weren't used for `unused_days` and tells it which
+    pub(super) fn gc(&mut self, unused_days: u32) -> Result<(), HandlerError> {
+        let root = self.root.clone();
+        let result = match self.collect(&root, DAY * unused_days, None, None) {
+            Ok(report) => {
+                info!(
+                    "Removed {} files unused for {unused_days} days, {} bytes freed",
+                    report.removed.len(),
+                    report.freed
+                );
+                GcResult::Collected(report)
+            }
+            Err(e) => {
+                warn!("Failed to collect unused files: {e}");
+                GcResult::Failed(e.to_string())
+            }
+        };
+
+        self.transport.encode_and_write(result)?;
+        Ok(())
+    }
+
+    /// Records that the file is in use, a failure is only logged
+    pub(super) fn mark_used(&self, path: &Path) {
+        if let Err(e) = storage::mark_used(path) {
+            warn!("Failed to mark {} as used: {e}", path.display());
+        }
+    }
+
+    /// Files under `dir` the server deployed
+    fn deployed(&self, dir: &Path) -> Vec<PathBuf> {
+        self.settings
+            .hash_index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .deployed(dir)
+    }
+
+    /// Removes the files the server deployed under `dir` and their kept versions that weren't
+    /// used for `unused_for`, least recently used first, until `shortage` bytes are free.
+    /// Leaves `keep`, and any file a client didn't deploy.
+    fn collect(
+        &self,
+        dir: &Path,
+        unused_for: Duration,
+        shortage: Option<u64>,
+        keep: Option<&Path>,
+    ) -> io::Result<GcReport> {
+        let now = SystemTime::now();
+        let versions = self.settings.versions.dir(dir)?;
+        let candidates =
+            self.settings
+                .storage
+                .candidates(&self.deployed(dir), &versions, unused_for, now)?;
+
+        let mut report = GcReport::default();
+        for candidate in candidates {
+            if shortage.is_some_and(|shortage| report.freed >= shortage) {
+                break;
+            }
+            if keep == Some(candidate.path.as_path()) {
+                continue;
+            }
+
+            match storage::remove(&candidate.path) {
+                Ok(freed) => report.freed += freed,
+                Err(e) => {
+                    warn!("Failed to remove {}: {e}", candidate.path.display());
+                    continue;
+                }
+            }
+            self.settings
+                .hash_index
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&candidate.path);
+
+            let path = candidate
+                .path
+                .strip_prefix(dir)
+                .or_else(|_| candidate.path.strip_prefix(&self.settings.root))
+                .unwrap_or(&candidate.path);
+            report.removed.push(RemovedFile {
+                path: path.to_owned(),
+                size: candidate.size,
+                unused_secs: now
+                    .duration_since(candidate.last_used)
+                    .unwrap_or_default()
+                    .as_secs(),
+            });
+        }
+        Ok(report)
+    }
+}
@@ -1,52 +1,177 @@
 use crate::{
-    protocol::{Action, MatchStatus, PathStatus, Request},
+    protocol::{
+        Action, ExitStatus, MatchStatus, PathStatus, Request, SpaceStatus, UPDATE_HEADER,
+        Validation, VersionHeader,
+    },
+    server::{
+        audit::AuditRecord,
+        ev3server::{Hook, HookEvent, Settings},
+    },
     transport::{Transport, TransportError},
 };
 use bincode::error::{DecodeError, EncodeError};
-use std::{io::Error, net::TcpStream};
-use tracing::{debug, info};
+use std::{
+    io::Error,
+    net::{SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
 
 pub struct ClientHandler {
     pub(super) transport: Transport,
-    pub(super) password: [u8; 32],
+    pub(super) peer: SocketAddr,
+    pub(super) settings: Arc<Settings>,
+    /// Canonical directory the client's paths are relative to, the root of its account
+    pub(super) root: PathBuf,
+    pub(super) audit: AuditRecord,
 }
 
 impl ClientHandler {
-    pub fn new(socket: TcpStream, password: [u8; 32]) -> Self {
-        let transport = Transport::new(socket);
+    pub fn new(socket: TcpStream, peer: SocketAddr, settings: Arc<Settings>) -> Self {
+        let transport = Transport::new(socket).with_limits(settings.limits);
+        let root = settings.root.clone();
         Self {
             transport,
-            password,
+            peer,
+            settings,
+            root,
+            audit: AuditRecord::new(peer),
         }
     }
 
     pub fn handle_client(&mut self) -> Result<(), HandlerError> {
-        self.check_version()?;
+        let started = Instant::now();
+        let result = self.handle();
+        self.settings
+            .program
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        self.write_audit(started, &result);
+        result
+    }
+
+    fn handle(&mut self) -> Result<(), HandlerError> {
+        self.start_handshake()?;
+        let VersionHeader(client_version) = self.transport.read_and_decode()?;
+        if client_version == UPDATE_HEADER {
+            return self.update();
+        }
+        self.check_version(&client_version)?;
+        // The client only hashes the file once it knows the accepted algorithms, which may
+        // take longer than the handshake timeout, so the request gets a deadline of its own
+        self.set_handshake_deadline();
 
         let req: Request = self.transport.read_and_decode()?;
         debug!("Received request header: {req:?}");
+        self.audit.request(&req);
 
         let (validation, safe_path) = self.validation(&req)?;
+        self.finish_handshake()?;
 
-        if validation.hash == MatchStatus::Mismatch {
-            self.download(&safe_path, req.use_compression)?;
-            info!("File received successfully");
+        match &req.action {
+            Action::Status => self.status()?,
+            Action::Delete => self.delete(&safe_path)?,
+            Action::History => self.history(&safe_path)?,
+            Action::Rollback(hash) => self.rollback(&safe_path, hash.as_deref())?,
+            Action::Gc(unused_days) => self.gc(*unused_days)?,
+            Action::Upload => self.upload(&req, &safe_path, validation)?,
+            Action::Run(program) | Action::Exec(program) => {
+                if req.action.uploads() {
+                    self.upload(&req, &safe_path, validation)?;
+                }
+                self.call_hooks(&self.settings.hooks.before_run, &req, &safe_path, None);
+                self.mark_used(&safe_path);
+                self.run(&req, &safe_path, program)?;
+            }
         }
 
-        #[cfg(unix)]
-        self.set_permissions(&safe_path)?;
+        info!("Done with this client");
+        Ok(())
+    }
 
-        if req.action == Action::Upload {
-            info!("Done with this client");
-            return Ok(());
+    /// Receives the file if validation found that the client's one differs
+    fn upload(
+        &mut self,
+        req: &Request,
+        safe_path: &Path,
+        validation: Validation,
+    ) -> Result<(), HandlerError> {
+        let received_hash = if validation.hash == MatchStatus::Mismatch {
+            self.call_hooks(&self.settings.hooks.before_upload, req, safe_path, None);
+            self.keep_version(safe_path);
+            let hash_algorithm = self.received_hash_algorithm(req);
+            // Validation refuses uploads without a size
+            let size = req.size.unwrap_or(0);
+            let expected = req.hash.filter(|hash| hash.algorithm() == hash_algorithm);
+            let (hash, bytes) =
+                self.download(safe_path, req.compression, hash_algorithm, size, expected)?;
+            info!("File received successfully");
+            self.audit.hash = Some(hash.to_string());
+            self.audit.bytes = bytes;
+            Some(hash)
+        } else {
+            None
+        };
+
+        self.audit.skipped = Some(received_hash.is_none());
+        if received_hash.is_none() {
+            self.mark_used(safe_path);
         }
 
-        if let Action::Run(brickrun) = req.action {
-            self.run(&safe_path, brickrun)?;
+        #[cfg(unix)]
+        self.set_permissions(safe_path)?;
+
+        if let Some(hash) = received_hash {
+            if let Err(e) = self.index_hash(safe_path, hash) {
+                warn!("Failed to index the hash of {}: {e}", safe_path.display());
+            }
+            self.call_hooks(&self.settings.hooks.after_upload, req, safe_path, None);
         }
+        Ok(())
+    }
 
+    /// The handshake has to be done before the deadline, however slowly the client sends it
+    fn start_handshake(&mut self) -> Result<(), Error> {
+        let timeouts = self.settings.timeouts;
+        self.transport.set_keepalive(timeouts.keepalive)?;
+        self.transport.set_write_timeout(timeouts.handshake)?;
+        self.set_handshake_deadline();
         Ok(())
     }
+
+    fn set_handshake_deadline(&mut self) {
+        let handshake = self.settings.timeouts.handshake;
+        self.transport
+            .set_deadline(handshake.map(|timeout| Instant::now() + timeout));
+    }
+
+    pub(super) fn finish_handshake(&mut self) -> Result<(), Error> {
+        let idle = self.settings.timeouts.idle;
+        self.transport.set_deadline(None);
+        self.transport.set_read_timeout(idle)?;
+        self.transport.set_write_timeout(idle)
+    }
+
+    pub(super) fn call_hooks(
+        &self,
+        hooks: &[Hook],
+        request: &Request,
+        path: &Path,
+        exit_status: Option<ExitStatus>,
+    ) {
+        let event = HookEvent {
+            request,
+            path,
+            peer: self.peer,
+            exit_status,
+        };
+        for hook in hooks {
+            hook(&event);
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -61,8 +186,18 @@ pub enum HandlerError {
     Io(#[from] Error),
     #[error("Password hashes don't match")]
     PasswordsDontMatch,
+    #[error("Client is locked out after too many wrong passwords")]
+    LockedOut,
+    #[error("Account {0} isn't allowed to do this")]
+    Forbidden(String),
     #[error("Version mismatch: {0}")]
     VersionMismatch(String),
     #[error("Path validation error: {0}")]
     PathValidation(#[from] PathStatus),
+    #[error("Update failed: {0}")]
+    Update(String),
+    #[error("Received file doesn't match the request: {0}")]
+    ReceivedFile(String),
+    #[error("Upload refused: {0}")]
+    UploadRefused(SpaceStatus),
 }
@@ -1,23 +1,59 @@
 use super::ClientHandler;
-use crate::{hash::Hasher, protocol::MatchStatus};
+use crate::{
+    hash::Hasher,
+    protocol::{Digest, HashAlgorithm, MatchStatus, Request},
+};
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{self, BufReader},
     path::Path,
 };
 use tracing::{debug, trace, warn};
 
 impl ClientHandler {
-    pub(super) fn check_hash(path: &Path, remote_hash: u64) -> Result<MatchStatus, io::Error> {
-        if !path.exists() || path.is_dir() {
+    pub(super) fn check_hash(
+        &self,
+        path: &Path,
+        remote_hash: Option<Digest>,
+    ) -> Result<MatchStatus, io::Error> {
+        let Some(remote_hash) = remote_hash else {
+            return Ok(MatchStatus::Mismatch);
+        };
+
+        // A digest of an algorithm we don't trust could be a crafted collision, so the file
+        // is always uploaded again
+        let algorithm = remote_hash.algorithm();
+        if !self.settings.hash_algorithms.contains(&algorithm) {
+            warn!("Client sent a {algorithm} hash, which isn't accepted, requesting the file");
             return Ok(MatchStatus::Mismatch);
         }
 
-        let file = File::open(path).inspect_err(|e| warn!("Failed to open the file: {e}"))?;
-        let mut reader = BufReader::new(file);
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Ok(MatchStatus::Mismatch),
+        };
 
-        let hash = Hasher::hash_file(&mut reader)
-            .inspect_err(|e| warn!("Failed to calculate hash of the file: {e}"))?;
+        let mut hash_index = self
+            .settings
+            .hash_index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let hash = match hash_index.get(path, &metadata, algorithm) {
+            Some(hash) => {
+                debug!("Using the indexed hash of {}", path.display());
+                hash
+            }
+            None => {
+                let file =
+                    File::open(path).inspect_err(|e| warn!("Failed to open the file: {e}"))?;
+                let mut reader = BufReader::new(file);
+
+                let hash = Hasher::hash_file(algorithm, &mut reader)
+                    .inspect_err(|e| warn!("Failed to calculate hash of the file: {e}"))?;
+                hash_index.insert(path, &metadata, hash);
+                hash
+            }
+        };
 
         trace!("hash: {hash} / remote_hash: {remote_hash}");
         if hash != remote_hash {
@@ -28,4 +64,43 @@ impl ClientHandler {
         debug!("Hashes match");
         Ok(MatchStatus::Match)
     }
+
+    /// Hash of the file from the index, or hashed with the preferred algorithm and indexed if
+    /// it changed since it was hashed
+    pub(super) fn file_hash(&self, path: &Path) -> Result<Digest, io::Error> {
+        let metadata = fs::metadata(path)?;
+        let mut hash_index = self
+            .settings
+            .hash_index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(hash) = hash_index.hash(path, &metadata) {
+            return Ok(hash);
+        }
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let hash = Hasher::hash_file(self.settings.hash_algorithms[0], &mut reader)?;
+        hash_index.insert(path, &metadata, hash);
+        Ok(hash)
+    }
+
+    /// Adds the hash of a file that was just deployed to the index
+    pub(super) fn index_hash(&self, path: &Path, hash: Digest) -> Result<(), io::Error> {
+        let metadata = fs::metadata(path)?;
+        self.settings
+            .hash_index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .deploy(path, &metadata, hash);
+        Ok(())
+    }
+
+    /// Algorithm to hash a received file with, the one of the client if it is accepted
+    pub(super) fn received_hash_algorithm(&self, request: &Request) -> HashAlgorithm {
+        request
+            .hash
+            .map(|hash| hash.algorithm())
+            .filter(|algorithm| self.settings.hash_algorithms.contains(algorithm))
+            .unwrap_or(self.settings.hash_algorithms[0])
+    }
 }
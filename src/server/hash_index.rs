@@ -0,0 +1,241 @@
+use crate::{
+    hash::modified_ns,
+    protocol::{Digest, HashAlgorithm},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+/// Directory under the server root the server keeps its own files in, clients can't use it
+pub(super) const STATE_DIR: &str = ".ev3-runner";
+const INDEX_FILE: &str = "hash-index.json";
+
+/// Hashes of the files under the server root, so unchanged files don't have to be read again.
+///
+/// An entry is only used while the size, mtime and inode of the file are the same as when it
+/// was hashed. It also records whether the server deployed the file, only those are collected
+/// as garbage.
+#[derive(Debug)]
+pub(super) struct HashIndex {
+    root: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_ns: u64,
+    inode: u64,
+    hash: Digest,
+    /// Received from a client or restored from a kept version
+    #[serde(default)]
+    deployed: bool,
+}
+
+impl Entry {
+    fn new(metadata: &Metadata, hash: Digest, deployed: bool) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime_ns: modified_ns(metadata),
+            inode: inode(metadata),
+            hash,
+            deployed,
+        }
+    }
+
+    /// Whether the file didn't change since it was hashed
+    fn matches(&self, metadata: &Metadata) -> bool {
+        (self.size, self.mtime_ns, self.inode)
+            == (metadata.len(), modified_ns(metadata), inode(metadata))
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+impl HashIndex {
+    /// Loads the index of the canonical `root`, starting over if it is missing or broken
+    pub(super) fn load(root: &Path) -> Self {
+        let mut index = Self {
+            root: root.to_owned(),
+            entries: HashMap::new(),
+        };
+
+        let file = index.file();
+        match fs::read(&file) {
+            Ok(content) => match serde_json::from_slice::<HashMap<PathBuf, Entry>>(&content) {
+                Ok(entries) => index.entries = entries,
+                Err(e) => warn!("Ignoring broken hash index {}: {e}", file.display()),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read hash index {}: {e}", file.display()),
+        }
+
+        let count = index.entries.len();
+        index
+            .entries
+            .retain(|path, _| index.root.join(path).is_file());
+        debug!(
+            "Loaded hash index with {} entries, dropped {} of removed files",
+            index.entries.len(),
+            count - index.entries.len()
+        );
+
+        index
+    }
+
+    /// Hash of the file at the absolute `path` if it didn't change since it was hashed with
+    /// `algorithm`
+    pub(super) fn get(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        algorithm: HashAlgorithm,
+    ) -> Option<Digest> {
+        self.hash(path, metadata)
+            .filter(|hash| hash.algorithm() == algorithm)
+    }
+
+    /// Hash of the file at the absolute `path` if it didn't change since it was hashed, with
+    /// whatever algorithm that was
+    pub(super) fn hash(&self, path: &Path, metadata: &Metadata) -> Option<Digest> {
+        let entry = self.entries.get(path.strip_prefix(&self.root).ok()?)?;
+        entry.matches(metadata).then_some(entry.hash)
+    }
+
+    /// Remembers the hash of the file at the absolute `path` and saves the index. It stays
+    /// deployed if it didn't change since it was deployed.
+    pub(super) fn insert(&mut self, path: &Path, metadata: &Metadata, hash: Digest) {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+
+        let deployed = self
+            .entries
+            .get(relative)
+            .is_some_and(|entry| entry.deployed && entry.matches(metadata));
+        self.set(relative, Entry::new(metadata, hash, deployed));
+    }
+
+    /// Remembers the hash of the file at the absolute `path`, which the server just deployed,
+    /// and saves the index
+    pub(super) fn deploy(&mut self, path: &Path, metadata: &Metadata, hash: Digest) {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+
+        self.set(relative, Entry::new(metadata, hash, true));
+    }
+
+    /// Absolute paths of the files under the absolute `dir` the server deployed
+    pub(super) fn deployed(&self, dir: &Path) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.deployed)
+            .map(|(path, _)| self.root.join(path))
+            .filter(|path| path.starts_with(dir))
+            .collect()
+    }
+
+    fn set(&mut self, relative: &Path, entry: Entry) {
+        self.entries.insert(relative.to_owned(), entry);
+        if let Err(e) = self.save() {
+            warn!("Failed to save the hash index: {e}");
+        }
+    }
+
+    /// Forgets the file at the absolute `path` and saves the index
+    pub(super) fn remove(&mut self, path: &Path) {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+
+        if self.entries.remove(relative).is_some()
+            && let Err(e) = self.save()
+        {
+            warn!("Failed to save the hash index: {e}");
+        }
+    }
+
+    fn save(&self) -> Result<(), io::Error> {
+        let file = self.file();
+        fs::create_dir_all(self.root.join(STATE_DIR))?;
+
+        // Write to a temporary file first, so a crash doesn't leave a truncated index
+        let tmp = file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&self.entries)?)?;
+        fs::rename(&tmp, &file)
+    }
+
+    fn file(&self) -> PathBuf {
+        self.root.join(STATE_DIR).join(INDEX_FILE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_index_survives_restart_and_detects_changes() {
+        let tmp = TempDir::new("hash-index");
+        let root = tmp.path();
+        let path = root.join("program");
+        fs::write(&path, b"first").unwrap();
+
+        let hash = Digest::Blake3([42; 32]);
+        let mut index = HashIndex::load(root);
+        index.insert(&path, &fs::metadata(&path).unwrap(), hash);
+
+        let index = HashIndex::load(root);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(
+            index.get(&path, &metadata, HashAlgorithm::Blake3),
+            Some(hash)
+        );
+        assert_eq!(index.get(&path, &metadata, HashAlgorithm::Sha256), None);
+
+        fs::write(&path, b"second, longer").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(index.get(&path, &metadata, HashAlgorithm::Blake3), None);
+
+        fs::remove_file(&path).unwrap();
+        assert!(HashIndex::load(root).entries.is_empty());
+    }
+
+    #[test]
+    fn test_only_deployed_files_are_listed() {
+        let tmp = TempDir::new("hash-index-deployed");
+        let root = tmp.path();
+        let (deployed, hashed) = (root.join("deployed"), root.join("hashed"));
+        fs::write(&deployed, b"deployed").unwrap();
+        fs::write(&hashed, b"hashed").unwrap();
+
+        let mut index = HashIndex::load(root);
+        let hash = Digest::XxHash64(42);
+        index.deploy(&deployed, &fs::metadata(&deployed).unwrap(), hash);
+        index.insert(&hashed, &fs::metadata(&hashed).unwrap(), hash);
+        // Hashing it again with another algorithm doesn't forget that it was deployed
+        index.insert(
+            &deployed,
+            &fs::metadata(&deployed).unwrap(),
+            Digest::Blake3([7; 32]),
+        );
+
+        assert_eq!(HashIndex::load(root).deployed(root), [deployed]);
+        assert!(index.deployed(&root.join("other")).is_empty());
+    }
+}
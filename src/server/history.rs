@@ -0,0 +1,82 @@
+use crate::{
+    protocol::{FileHistory, FileVersion, RollbackResult},
+    server::{
+        handler::{ClientHandler, HandlerError},
+        versions,
+    },
+};
+use std::{io, path::Path, time::SystemTime};
+use tracing::{debug, info, warn};
+
+impl ClientHandler {
+    /// Keeps the deployed file before it is replaced or deleted, a failure is only logged
+    pub(super) fn keep_version(&self, path: &Path) {
+        let versions = &self.settings.versions;
+        if !versions.is_enabled() || !path.is_file() {
+            return;
+        }
+
+        if let Err(e) = self
+            .file_hash(path)
+            .and_then(|hash| versions.keep(path, hash))
+        {
+            warn!(
+                "Failed to keep the previous version of {}: {e}",
+                path.display()
+            );
+        }
+    }
+
+    /// Sends the deployed and the kept versions of the file
+    pub(super) fn history(&mut self, path: &Path) -> Result<(), HandlerError> {
+        let now = SystemTime::now();
+        let current = if path.is_file() {
+            Some(versions::deployed(path, self.file_hash(path)?, now)?)
+        } else {
+            None
+        };
+        let versions = self
+            .settings
+            .versions
+            .list(path)?
+            .iter()
+            .map(|kept| kept.version(now))
+            .collect::<Result<_, _>>()?;
+
+        let history = FileHistory { current, versions };
+        debug!("History of {}: {history:?}", path.display());
+        self.transport.encode_and_write(history)?;
+        Ok(())
+    }
+
+    /// Deploys a kept version of the file again and tells the client which one
+    pub(super) fn rollback(&mut self, path: &Path, hash: Option<&str>) -> Result<(), HandlerError> {
+        let result = match self.restore(path, hash) {
+            Ok(version) => {
+                info!("Rolled {} back to {}", path.display(), version.hash);
+                RollbackResult::Restored(version)
+            }
+            Err(e) => {
+                warn!("Failed to roll back {}: {e}", path.display());
+                RollbackResult::Failed(e.to_string())
+            }
+        };
+
+        self.transport.encode_and_write(result)?;
+        Ok(())
+    }
+
+    fn restore(&self, path: &Path, hash: Option<&str>) -> io::Result<FileVersion> {
+        let kept = self.settings.versions.list(path)?;
+        let kept = versions::select(&kept, hash)?;
+        let current = if path.is_file() {
+            Some(self.file_hash(path)?)
+        } else {
+            None
+        };
+
+        self.settings.versions.restore(kept, path, current)?;
+        self.index_hash(path, kept.hash())?;
+        versions::deployed(path, kept.hash(), SystemTime::now())
+    }
+}
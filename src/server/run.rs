@@ -1,52 +1,180 @@
-use crate::server::handler::{ClientHandler, HandlerError};
+use crate::{
+    protocol::{Program, Request},
+    server::{
+        ev3server::kill_process_group,
+        handler::{ClientHandler, HandlerError},
+    },
+};
 use std::{
-    io::{self},
+    io::{self, Read},
+    net::{Shutdown, TcpStream},
     path::Path,
-    process::Command,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
 };
 use tracing::{debug, info, warn};
 
 impl ClientHandler {
-    pub(super) fn run(&mut self, path: &Path, brickrun: bool) -> Result<(), HandlerError> {
-        debug!("Running the file at ./{}", path.display());
-
-        let (mut reader, writer) = io::pipe()?;
-
-        let arg = format!("./{}", path.display());
-        let mut child = if brickrun {
-            Command::new("brickrun")
-                .arg("-r")
-                .arg(arg)
-                .stdout(writer.try_clone()?)
-                .stderr(writer)
-                .spawn()
-                .inspect_err(|e| warn!("Failed to spawn brickrun command: {e}"))?
+    pub(super) fn run(
+        &mut self,
+        req: &Request,
+        path: &Path,
+        program: &Program,
+    ) -> Result<(), HandlerError> {
+        debug!("Running the file at {}", path.display());
+
+        let mut command = if program.brickrun {
+            let mut command = Command::new("brickrun");
+            command.arg("-r").arg(path);
+            command
         } else {
-            Command::new(arg)
-                .stdout(writer.try_clone()?)
-                .stderr(writer)
-                .spawn()
-                .inspect_err(|e| warn!("Failed to spawn command: {e}"))?
+            Command::new(path)
         };
+        command
+            .args(&program.args)
+            .current_dir(&self.root)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // In its own process group, so terminating the server also stops what it started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut child = command.spawn().inspect_err(|e| {
+            if program.brickrun {
+                warn!("Failed to spawn brickrun command: {e}")
+            } else {
+                warn!("Failed to spawn command: {e}")
+            }
+        })?;
+
+        let stdout = child.stdout.take().ok_or_else(missing_pipe)?;
+        let stderr = child.stderr.take().ok_or_else(missing_pipe)?;
+
+        // The watcher shares the socket and may wait for as long as the program runs,
+        // vanished clients are noticed by the keepalive probes instead
+        self.transport.set_read_timeout(None)?;
+
+        let child = Arc::new(Mutex::new(child));
+        *self
+            .settings
+            .program
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Arc::clone(&child));
+        let finished = Arc::new(AtomicBool::new(false));
+        let _watcher = watch_disconnect(
+            self.transport.stream.try_clone()?,
+            Arc::clone(&child),
+            Arc::clone(&finished),
+        )?;
 
-        if let Err(e) = self.transport.forward_output(&mut reader) {
+        if let Err(e) = self.transport.forward_output(stdout, stderr) {
             warn!("Failed to send output to client: {e}");
-            child.kill()?;
+            finished.store(true, Ordering::SeqCst);
+            let mut child = lock(&child);
+            kill_process_group(&mut child)?;
+            let status = child.wait()?;
+            self.audit.exit_status = Some(status.into());
+            self.call_hooks(
+                &self.settings.hooks.after_run,
+                req,
+                path,
+                Some(status.into()),
+            );
             return Err(e.into());
         }
 
-        let status = child
-            .wait()
-            .inspect_err(|e| warn!("Failed to wait for exit status of the child: {e}"))?;
+        let status = loop {
+            if let Some(status) = lock(&child)
+                .try_wait()
+                .inspect_err(|e| warn!("Failed to wait for exit status of the child: {e}"))?
+            {
+                break status;
+            }
+            thread::sleep(WAIT_INTERVAL);
+        };
+        finished.store(true, Ordering::SeqCst);
 
         if status.success() {
             info!("Child exited with exit status: {status}");
         } else {
             warn!("Child exited with exit status: {status}");
         }
+        self.audit.exit_status = Some(status.into());
+
+        self.call_hooks(
+            &self.settings.hooks.after_run,
+            req,
+            path,
+            Some(status.into()),
+        );
+        self.transport.send_exit_status(status.into())?;
 
-        debug!("Ran file at ./{}", path.display());
+        debug!("Ran file at {}", path.display());
 
         Ok(())
     }
 }
+
+fn missing_pipe() -> io::Error {
+    io::Error::other("Output of the spawned command is not piped")
+}
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Kills the child as soon as the client closes the connection, e.g. to restart it in watch mode
+fn watch_disconnect(
+    stream: TcpStream,
+    child: Arc<Mutex<Child>>,
+    finished: Arc<AtomicBool>,
+) -> io::Result<DisconnectWatcher> {
+    let watcher = DisconnectWatcher {
+        stream: stream.try_clone()?,
+    };
+    let mut stream = stream;
+    thread::Builder::new()
+        .name("disconnect-watcher".to_owned())
+        .spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n) = stream.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+
+            if finished.load(Ordering::SeqCst) {
+                return;
+            }
+            info!("Client disconnected, stopping the program");
+            if let Err(e) = kill_process_group(&mut lock(&child)) {
+                warn!("Failed to kill the program: {e}");
+            }
+        })?;
+    Ok(watcher)
+}
+
+/// Shuts the connection down when the run is over, which wakes the watcher thread up from its
+/// read so it doesn't outlive the run
+struct DisconnectWatcher {
+    stream: TcpStream,
+}
+
+impl Drop for DisconnectWatcher {
+    fn drop(&mut self) {
+        if let Err(e) = self.stream.shutdown(Shutdown::Both)
+            && e.kind() != io::ErrorKind::NotConnected
+        {
+            warn!("Failed to shut down the connection after the run: {e}");
+        }
+    }
+}
+
+fn lock(child: &Mutex<Child>) -> std::sync::MutexGuard<'_, Child> {
+    child
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,307 @@
+use crate::cli::{InstallServiceArgs, Server};
+use std::{
+    env,
+    ffi::OsString,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+};
+use tracing::{info, warn};
+
+/// First file descriptor systemd passes sockets as
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Writes the systemd units that start the server with the options given before
+/// `install-service`
+pub(super) fn install_service(config: &Server, args: &InstallServiceArgs) -> io::Result<()> {
+    if config.daemon {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--daemon can't be used with systemd, it keeps the server in the foreground",
+        ));
+    }
+
+    let exec_start = exec_start(env::current_exe()?, env::args_os().skip(1))?;
+    let working_dir = env::current_dir()?;
+    let name = &args.unit_name;
+    let env_file = args
+        .env_file
+        .clone()
+        .unwrap_or_else(|| Path::new("/etc/default").join(name));
+    let environment = environment(&config.password)?;
+    let service = service_unit(&exec_start, &working_dir, &env_file, args);
+    let socket = args
+        .socket_activation
+        .then(|| socket_unit(SocketAddr::new(config.bind, config.server_port)));
+
+    if args.print {
+        println!("# {}\n{environment}", env_file.display());
+        println!("# {name}.service\n{service}");
+        if let Some(socket) = socket {
+            println!("# {name}.socket\n{socket}");
+        }
+        return Ok(());
+    }
+
+    write_private(&env_file, &environment)
+        .inspect_err(|e| warn!("Failed to write {}: {e}", env_file.display()))?;
+    info!("Wrote {}", env_file.display());
+    let service_file = args.unit_dir.join(format!("{name}.service"));
+    fs::write(&service_file, service)
+        .inspect_err(|e| warn!("Failed to write {}: {e}", service_file.display()))?;
+    info!("Wrote {}", service_file.display());
+    let enable = match socket {
+        Some(socket) => {
+            let socket_file = args.unit_dir.join(format!("{name}.socket"));
+            fs::write(&socket_file, socket)
+                .inspect_err(|e| warn!("Failed to write {}: {e}", socket_file.display()))?;
+            info!("Wrote {}", socket_file.display());
+            format!("{name}.socket")
+        }
+        None => format!("{name}.service"),
+    };
+
+    println!("Start the server now and after every boot with:");
+    println!("  systemctl daemon-reload && systemctl enable --now {enable}");
+    Ok(())
+}
+
+/// Command line of the service: the server arguments of this process, without the password,
+/// which anyone could read in the unit, and without `install-service` and its options
+fn exec_start(
+    exe: impl Into<OsString>,
+    args: impl Iterator<Item = OsString>,
+) -> io::Result<String> {
+    let mut line = quote(&exe.into())?;
+    let mut password_follows = false;
+    for arg in args.take_while(|arg| arg != "install-service") {
+        if std::mem::take(&mut password_follows) {
+            continue;
+        }
+        let text = arg.to_string_lossy();
+        if text == "-p" || text == "--password" {
+            password_follows = true;
+            continue;
+        }
+        if text.starts_with("--password=") || (text.starts_with("-p") && !text.starts_with("--")) {
+            continue;
+        }
+
+        line.push(' ');
+        line.push_str(&quote(&arg)?);
+    }
+    Ok(line)
+}
+
+/// `EnvironmentFile` passing the password to the service
+fn environment(password: &str) -> io::Result<String> {
+    if password.contains(['\n', '\r']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The password can't contain line breaks",
+        ));
+    }
+
+    let mut escaped = String::new();
+    for c in password.chars() {
+        if matches!(c, '"' | '\\' | '`' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Ok(format!("EV3_RUNNER_PASSWORD=\"{escaped}\"\n"))
+}
+
+/// Writes a file only its owner can read
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+}
+
+/// Quotes an argument for `ExecStart`, escaping what systemd would expand
+fn quote(arg: &OsString) -> io::Result<String> {
+    let arg = arg.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Argument {arg:?} isn't valid UTF-8"),
+        )
+    })?;
+
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+".contains(c));
+    Ok(if plain {
+        escaped
+    } else {
+        format!("\"{escaped}\"")
+    })
+}
+
+fn service_unit(
+    exec_start: &str,
+    working_dir: &Path,
+    env_file: &Path,
+    args: &InstallServiceArgs,
+) -> String {
+    let mut unit = String::from("[Unit]\nDescription=ev3-runner server\nAfter=network.target\n");
+    if args.socket_activation {
+        let _ = writeln!(unit, "Requires={}.socket", args.unit_name);
+    }
+
+    unit.push_str("\n[Service]\nType=simple\n");
+    let _ = writeln!(unit, "EnvironmentFile={}", env_file.display());
+    let _ = writeln!(unit, "ExecStart={exec_start}");
+    let _ = writeln!(unit, "WorkingDirectory={}", working_dir.display());
+    if let Some(user) = &args.user {
+        let _ = writeln!(unit, "User={user}");
+    }
+    // The server stops the running program itself on SIGTERM
+    unit.push_str("KillMode=mixed\nRestart=on-failure\n");
+
+    // With socket activation the socket is enabled instead
+    if !args.socket_activation {
+        unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+    }
+    unit
+}
+
+fn socket_unit(addr: SocketAddr) -> String {
+    format!(
+        "[Unit]\nDescription=ev3-runner server socket\n\n\
+         [Socket]\nListenStream={addr}\n\n\
+         [Install]\nWantedBy=sockets.target\n"
+    )
+}
+
+/// The listening socket passed by systemd socket activation, if any
+#[cfg(unix)]
+pub(super) fn systemd_listener() -> io::Result<Option<TcpListener>> {
+    use socket2::SockRef;
+    use std::os::fd::FromRawFd;
+
+    let for_us = env::var("LISTEN_PID").is_ok_and(|pid| pid == std::process::id().to_string());
+    let count: u32 = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    if !for_us || count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("systemd passed {count} sockets, only using the first");
+    }
+
+    // SAFETY: systemd passes the sockets starting at this descriptor, and nothing else in
+    // this process owns it
+    let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    // Programs started by the server shouldn't inherit it
+    SockRef::from(&listener).set_cloexec(true)?;
+    info!("Using the inherited listening socket");
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+pub(super) fn systemd_listener() -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+/// Lets `command` find `listener` the way `systemd_listener` expects it, once it is executed
+/// in place of this process
+#[cfg(unix)]
+pub(super) fn pass_listener(
+    command: &mut std::process::Command,
+    listener: &TcpListener,
+) -> io::Result<()> {
+    use socket2::SockRef;
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    // SAFETY: dup2 only replaces the descriptor, this process is about to be replaced anyway
+    if unsafe { libc::dup2(listener.as_raw_fd(), SD_LISTEN_FDS_START) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: dup2 just made it a valid descriptor, which stays open until the exec
+    let fd = unsafe { BorrowedFd::borrow_raw(SD_LISTEN_FDS_START) };
+    SockRef::from(&fd).set_cloexec(false)?;
+
+    command
+        .env("LISTEN_PID", std::process::id().to_string())
+        .env("LISTEN_FDS", "1");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_start() {
+        let args = [
+            "-v",
+            "server",
+            "--root",
+            "my programs%$",
+            "-p",
+            "secret",
+            "--password=secret",
+            "-psecret",
+            "install-service",
+            "--print",
+        ];
+        let line = exec_start("/usr/bin/ev3-runner", args.into_iter().map(OsString::from)).unwrap();
+        assert_eq!(
+            line,
+            r#"/usr/bin/ev3-runner -v server --root "my programs%%$$""#
+        );
+    }
+
+    #[test]
+    fn test_environment() {
+        assert_eq!(
+            environment(r#"a"b\c$d"#).unwrap(),
+            "EV3_RUNNER_PASSWORD=\"a\\\"b\\\\c\\$d\"\n"
+        );
+        assert!(environment("a\nb").is_err());
+    }
+
+    #[test]
+    fn test_socket_activation_units() {
+        let args = InstallServiceArgs {
+            unit_dir: "/etc/systemd/system".into(),
+            unit_name: "ev3-runner".to_owned(),
+            env_file: None,
+            socket_activation: true,
+            user: Some("robot".to_owned()),
+            print: true,
+        };
+        let service = service_unit(
+            "/usr/bin/ev3-runner server",
+            Path::new("/home/robot"),
+            Path::new("/etc/default/ev3-runner"),
+            &args,
+        );
+        assert!(service.contains("Requires=ev3-runner.socket\n"));
+        assert!(service.contains("ExecStart=/usr/bin/ev3-runner server\n"));
+        assert!(service.contains("User=robot\n"));
+        assert!(service.contains("EnvironmentFile=/etc/default/ev3-runner\n"));
+        assert!(!service.contains("[Install]"));
+
+        let socket = socket_unit("0.0.0.0:6767".parse().unwrap());
+        assert!(socket.contains("ListenStream=0.0.0.0:6767\n"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    protocol::{Device, PowerSupply, RobotStatus},
+    server::handler::{ClientHandler, HandlerError},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+impl ClientHandler {
+    pub(super) fn status(&mut self) -> Result<(), HandlerError> {
+        let status = read_status(&self.settings.sysfs_root)
+            .inspect_err(|e| warn!("Failed to read the robot status: {e}"))?;
+        debug!("Read robot status: {status:?}");
+
+        self.transport.encode_and_write(status)?;
+        Ok(())
+    }
+}
+
+/// Reads the battery, motor and sensor information from an ev3dev sysfs tree.
+///
+/// Missing classes (e.g. no motor plugged in) result in empty lists.
+pub fn read_status(sysfs_root: &Path) -> Result<RobotStatus, io::Error> {
+    let class = sysfs_root.join("class");
+
+    let power_supplies = class_entries(&class.join("power_supply"))?
+        .into_iter()
+        .map(|dir| PowerSupply {
+            name: file_name(&dir),
+            voltage: read_attr(&dir, "voltage_now").and_then(|v| v.parse().ok()),
+            current: read_attr(&dir, "current_now").and_then(|v| v.parse().ok()),
+            technology: read_attr(&dir, "technology"),
+        })
+        .collect();
+
+    Ok(RobotStatus {
+        power_supplies,
+        motors: read_devices(&class.join("tacho-motor"))?,
+        sensors: read_devices(&class.join("lego-sensor"))?,
+    })
+}
+
+fn read_devices(dir: &Path) -> Result<Vec<Device>, io::Error> {
+    let mut devices: Vec<Device> = class_entries(dir)?
+        .into_iter()
+        .filter_map(|dir| {
+            Some(Device {
+                address: read_attr(&dir, "address")?,
+                driver_name: read_attr(&dir, "driver_name")?,
+                mode: read_attr(&dir, "mode"),
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.address.cmp(&b.address));
+    Ok(devices)
+}
+
+/// Lists the device directories of a sysfs class, sorted by name
+fn class_entries(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    fs::read_to_string(dir.join(attr))
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test/sysfs");
+
+    #[test]
+    fn test_reads_fixture_tree() {
+        let status = read_status(Path::new(FIXTURE)).unwrap();
+
+        assert_eq!(status.power_supplies.len(), 1);
+        let battery = &status.power_supplies[0];
+        assert_eq!(battery.name, "lego-ev3-battery");
+        assert_eq!(battery.voltage, Some(7_831_600));
+        assert_eq!(battery.technology.as_deref(), Some("Li-ion"));
+
+        let motors: Vec<_> = status.motors.iter().map(|m| m.address.as_str()).collect();
+        assert_eq!(motors, ["ev3-ports:outA", "ev3-ports:outD"]);
+        assert_eq!(status.motors[1].driver_name, "lego-ev3-m-motor");
+
+        assert_eq!(status.sensors.len(), 1);
+        assert_eq!(status.sensors[0].driver_name, "lego-ev3-touch");
+        assert_eq!(status.sensors[0].mode.as_deref(), Some("TOUCH"));
+    }
+
+    #[test]
+    fn test_missing_classes_are_empty() {
+        let status = read_status(Path::new("/nonexistent-sysfs-root")).unwrap();
+        assert_eq!(status, RobotStatus::default());
+    }
+}
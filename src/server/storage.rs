@@ -0,0 +1,291 @@
+use crate::protocol::SpaceStatus;
+use std::{
+    collections::HashSet,
+    fs::{self, File, FileTimes, Metadata},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::debug;
+
+/// Space taken up by the files the server deployed and their kept versions
+#[derive(Debug)]
+pub(super) struct Storage {
+    root: PathBuf,
+    /// Bytes the deployed files and kept versions may take up together, `None` for no limit
+    quota: Option<u64>,
+    /// Files unused for this long are removed when an upload wouldn't fit otherwise
+    auto_gc: Option<Duration>,
+}
+
+/// A file that can be collected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Candidate {
+    pub(super) path: PathBuf,
+    pub(super) size: u64,
+    pub(super) last_used: SystemTime,
+}
+
+impl Storage {
+    /// Storage of the canonical `root`
+    pub(super) fn new(root: &Path, quota: Option<u64>, auto_gc: Option<Duration>) -> Self {
+        Self {
+            root: root.to_owned(),
+            quota,
+            auto_gc,
+        }
+    }
+
+    pub(super) fn has_quota(&self) -> bool {
+        self.quota.is_some()
+    }
+
+    pub(super) fn auto_gc(&self) -> Option<Duration> {
+        self.auto_gc
+    }
+
+    /// Whether `needed` more bytes fit into the quota and onto the disk, given the `deployed`
+    /// files and the kept versions in `versions`
+    pub(super) fn check(
+        &self,
+        needed: u64,
+        deployed: &[PathBuf],
+        versions: &Path,
+    ) -> io::Result<SpaceStatus> {
+        if let Some(quota) = self.quota {
+            let available = quota.saturating_sub(used(&files(deployed, versions)?));
+            if needed > available {
+                return Ok(SpaceStatus::QuotaExceeded { needed, available });
+            }
+        }
+
+        if let Some(available) = free_space(&self.root)?
+            && needed > available
+        {
+            return Ok(SpaceStatus::DiskFull { needed, available });
+        }
+        Ok(SpaceStatus::Available)
+    }
+
+    /// The `deployed` files and the kept versions in `versions` that weren't used for
+    /// `unused_for`, least recently used first
+    pub(super) fn candidates(
+        &self,
+        deployed: &[PathBuf],
+        versions: &Path,
+        unused_for: Duration,
+        now: SystemTime,
+    ) -> io::Result<Vec<Candidate>> {
+        let mut candidates: Vec<_> = files(deployed, versions)?
+            .into_iter()
+            .map(|(path, metadata)| Candidate {
+                path,
+                size: metadata.len(),
+                last_used: last_used(&metadata),
+            })
+            .filter(|candidate| {
+                now.duration_since(candidate.last_used)
+                    .is_ok_and(|unused| unused >= unused_for)
+            })
+            .collect();
+        candidates.sort_by(|a, b| (a.last_used, &a.path).cmp(&(b.last_used, &b.path)));
+        Ok(candidates)
+    }
+}
+
+/// The `deployed` files that still exist and the files under `versions`. Only the kept
+/// versions are walked, never the whole root.
+fn files(deployed: &[PathBuf], versions: &Path) -> io::Result<Vec<(PathBuf, Metadata)>> {
+    let mut files = Vec::new();
+    for path in deployed {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => files.push((path.clone(), metadata)),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    walk(versions, &mut files)?;
+    Ok(files)
+}
+
+/// Bytes taken up by the files, files linked several times count once
+fn used(files: &[(PathBuf, Metadata)]) -> u64 {
+    let mut seen = HashSet::new();
+    files
+        .iter()
+        .filter(|(_, metadata)| file_id(metadata).is_none_or(|id| seen.insert(id)))
+        .map(|(_, metadata)| metadata.len())
+        .sum()
+}
+
+/// Removes the file and returns the bytes that became free, none if it is linked elsewhere
+pub(super) fn remove(path: &Path) -> io::Result<u64> {
+    let freed = freed_by_removing(path);
+    fs::remove_file(path)?;
+    debug!("Removed {}, {freed} bytes freed", path.display());
+    Ok(freed)
+}
+
+/// Bytes that become free when the file is removed or replaced
+pub(super) fn freed_by_removing(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() && links(&metadata) <= 1 => metadata.len(),
+        _ => 0,
+    }
+}
+
+/// Records that the file is in use, as the time of the last access, which the file system
+/// may not update itself
+pub(super) fn mark_used(path: &Path) -> io::Result<()> {
+    // Only read access, the file may be running
+    File::open(path)?.set_times(FileTimes::new().set_accessed(SystemTime::now()))
+}
+
+/// When the file was last uploaded, run or skipped
+fn last_used(metadata: &Metadata) -> SystemTime {
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    metadata
+        .accessed()
+        .map_or(modified, |accessed| accessed.max(modified))
+}
+
+/// Collects the regular files under `dir`. A missing `dir` has no files.
+fn walk(dir: &Path, files: &mut Vec<(PathBuf, Metadata)>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        // Links aren't followed, what they point to may be outside of the root
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            walk(&path, files)?;
+        } else if metadata.is_file() {
+            files.push((path, metadata));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn links(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &Metadata) -> u64 {
+    1
+}
+
+/// Bytes the server may still write to the file system of `path`
+#[cfg(unix)]
+fn free_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is only read after statvfs filled it in
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded
+    let stat = unsafe { stat.assume_init() };
+    // The field types differ between platforms
+    let free = stat.f_bavail as u128 * stat.f_frsize as u128;
+    Ok(Some(u64::try_from(free).unwrap_or(u64::MAX)))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_used_and_candidates() {
+        let tmp = TempDir::new("storage");
+        let root = tmp.path();
+        let versions = root.join("versions");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(versions.join("old")).unwrap();
+        let storage = Storage::new(root, Some(100), None);
+        let deployed = [root.join("old"), root.join("older"), root.join("dir/new")];
+        let now = SystemTime::now();
+
+        for (name, size, days) in [
+            ("old", 10, 20),
+            ("older", 20, 30),
+            ("dir/new", 30, 1),
+            ("versions/old/1-xxhash-000000000000002a", 5, 40),
+            // Not deployed by the server, like its own audit log
+            ("audit.jsonl", 50, 60),
+        ] {
+            let path = root.join(name);
+            fs::write(&path, vec![0; size]).unwrap();
+            let used = now - Duration::from_secs(days * 86_400);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_times(FileTimes::new().set_accessed(used).set_modified(used))
+                .unwrap();
+        }
+
+        assert_eq!(used(&files(&deployed, &versions).unwrap()), 65);
+        assert_eq!(
+            storage.check(40, &deployed, &versions).unwrap(),
+            SpaceStatus::QuotaExceeded {
+                needed: 40,
+                available: 35
+            }
+        );
+        assert_eq!(
+            storage.check(35, &deployed, &versions).unwrap(),
+            SpaceStatus::Available
+        );
+
+        let candidates = storage
+            .candidates(&deployed, &versions, Duration::from_secs(7 * 86_400), now)
+            .unwrap();
+        let paths: Vec<_> = candidates.iter().map(|c| c.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                versions.join("old/1-xxhash-000000000000002a"),
+                root.join("older"),
+                root.join("old")
+            ]
+        );
+
+        mark_used(&root.join("older")).unwrap();
+        assert_eq!(remove(&root.join("old")).unwrap(), 10);
+        let candidates = storage
+            .candidates(&deployed, &versions, Duration::from_secs(7 * 86_400), now)
+            .unwrap();
+        assert_eq!(candidates.len(), 1);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    VERSION,
+    protocol::{
+        AuthStatus, Compression, Digest, HashAlgorithm, UpdateRequest, UpdateResponse,
+        VersionResponse, VersionStatus,
+    },
+    server::{
+        Permission,
+        handler::{ClientHandler, HandlerError},
+    },
+};
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// Set for a server started by an update, names the backup of the previous binary
+const ROLLBACK_ENV: &str = "EV3_RUNNER_ROLLBACK";
+/// How long `<new binary> --version` may take
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl ClientHandler {
+    /// Receives a new binary, installs it in place of the running one and asks the server to
+    /// restart with it
+    pub(super) fn update(&mut self) -> Result<(), HandlerError> {
+        self.transport
+            .encode_and_write(VersionResponse(VersionStatus::Match))?;
+        let req: UpdateRequest = self.transport.read_and_decode()?;
+        debug!("Received update request: {req:?}");
+        self.audit.update(&req);
+
+        let auth = self.authenticate(req.user.as_ref(), &req.password, Some(Permission::Update));
+        self.audit.auth(auth);
+        let unavailable = if !self.settings.allow_update {
+            Some("Updates are disabled on this server")
+        } else if cfg!(not(unix)) {
+            Some("Updates are only supported on Unix")
+        } else {
+            None
+        };
+        let (response, error) = match auth {
+            AuthStatus::Match => match unavailable {
+                None => return self.install_update(&req),
+                Some(reason) => (
+                    UpdateResponse::Failed(reason.to_owned()),
+                    HandlerError::Update(reason.to_owned()),
+                ),
+            },
+            AuthStatus::Mismatch => (
+                UpdateResponse::WrongPassword,
+                HandlerError::PasswordsDontMatch,
+            ),
+            AuthStatus::LockedOut { retry_after_secs } => (
+                UpdateResponse::LockedOut { retry_after_secs },
+                HandlerError::LockedOut,
+            ),
+            AuthStatus::Forbidden => {
+                let user = req.user.clone().unwrap_or_default();
+                warn!("Account {user} isn't allowed to update the server");
+                (UpdateResponse::Forbidden, HandlerError::Forbidden(user))
+            }
+        };
+        self.transport.encode_and_write(response)?;
+        Err(error)
+    }
+
+    fn install_update(&mut self, req: &UpdateRequest) -> Result<(), HandlerError> {
+        let executable = env::current_exe()
+            .inspect_err(|e| warn!("Failed to find the server executable: {e}"))?;
+        self.finish_handshake()?;
+        self.transport.encode_and_write(UpdateResponse::Ready {
+            executable: executable.clone(),
+        })?;
+
+        let staged = with_suffix(&executable, "new");
+        let installed = self
+            .receive_binary(&staged, req)
+            .and_then(|()| install(&executable, &staged));
+        let version = match installed {
+            Ok(version) => version,
+            Err(e) => {
+                if let Err(e) = fs::remove_file(&staged)
+                    && e.kind() != io::ErrorKind::NotFound
+                {
+                    warn!("Failed to remove {}: {e}", staged.display());
+                }
+                // The client may be gone already if the transfer failed
+                let reason = match &e {
+                    HandlerError::Update(reason) | HandlerError::ReceivedFile(reason) => {
+                        reason.clone()
+                    }
+                    e => e.to_string(),
+                };
+                let response = UpdateResponse::Failed(reason);
+                if let Err(e) = self.transport.encode_and_write(response) {
+                    debug!("Failed to tell the client the update failed: {e}");
+                }
+                return Err(e);
+            }
+        };
+
+        info!("Installed ev3-runner {version}, restarting");
+        self.settings.restart.store(true, Ordering::SeqCst);
+        self.transport.encode_and_write(UpdateResponse::Installed {
+            version,
+            previous: VERSION.to_owned(),
+        })?;
+        Ok(())
+    }
+
+    /// Receives the binary into `path`, only if it is the one the client announced
+    fn receive_binary(&mut self, path: &Path, req: &UpdateRequest) -> Result<(), HandlerError> {
+        let (_, bytes) = self.download(
+            path,
+            Compression::None,
+            HashAlgorithm::Sha256,
+            req.size,
+            Some(Digest::Sha256(req.sha256)),
+        )?;
+        self.audit.bytes = bytes;
+
+        #[cfg(unix)]
+        self.set_permissions(path)?;
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Checks that the staged binary runs on this machine, keeps the current one as
+/// `<executable>.old` and moves the staged one in its place. Returns the new version.
+fn install(executable: &Path, staged: &Path) -> Result<String, HandlerError> {
+    let version = preflight(staged)?;
+
+    let backup = with_suffix(executable, "old");
+    if let Err(e) = fs::remove_file(&backup)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        return Err(e.into());
+    }
+    if let Err(e) = fs::hard_link(executable, &backup) {
+        debug!("Failed to link the backup, copying it instead: {e}");
+        fs::copy(executable, &backup)?;
+    }
+
+    fs::rename(staged, executable)
+        .inspect_err(|e| warn!("Failed to replace {}: {e}", executable.display()))?;
+    if let Some(dir) = executable.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(version)
+}
+
+/// Runs `<binary> --version`, which fails if it was built for another platform, and returns
+/// the version it prints
+fn preflight(binary: &Path) -> Result<String, HandlerError> {
+    let failed = |reason: String| HandlerError::Update(format!("The new binary {reason}"));
+
+    let mut child = Command::new(binary)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| failed(format!("doesn't start: {e}")))?;
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= PREFLIGHT_TIMEOUT {
+            child.kill()?;
+            child.wait()?;
+            return Err(failed("didn't print its version in time".to_owned()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if !status.success() {
+        return Err(failed(format!("failed to print its version ({status})")));
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        io::Read::read_to_string(&mut stdout, &mut output)?;
+    }
+    parse_version(&output)
+        .map(str::to_owned)
+        .ok_or_else(|| failed("isn't ev3-runner".to_owned()))
+}
+
+/// The version in the output of `ev3-runner --version`
+fn parse_version(output: &str) -> Option<&str> {
+    output
+        .trim()
+        .strip_prefix("ev3-runner ")
+        .filter(|version| !version.is_empty() && !version.contains(char::is_whitespace))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Executes the installed binary with the arguments of this process in place of it, passing
+/// on the listener so no connection is refused meanwhile. Goes back to the previous binary if
+/// the new one can't be executed. Only returns if neither could.
+pub(super) fn restart(executable: &Path, listener: &TcpListener) -> io::Error {
+    let backup = with_suffix(executable, "old");
+    let mut command = Command::new(executable);
+    command.env(ROLLBACK_ENV, &backup);
+    let error = exec(command, Some(listener));
+    warn!("Failed to start the new binary: {error}");
+
+    let rollback_error = restore(executable, &backup, Some(listener));
+    warn!("Failed to go back to the previous binary: {rollback_error}");
+    error
+}
+
+/// Goes back to the previous binary if this server was started by an update, otherwise
+/// returns `error`, which made the server fail to start
+pub(super) fn roll_back(
+    executable: &Path,
+    listener: Option<&TcpListener>,
+    error: io::Error,
+) -> io::Error {
+    let Some(backup) = env::var_os(ROLLBACK_ENV) else {
+        return error;
+    };
+
+    warn!("The new binary failed to start ({error}), going back to the previous one");
+    let rollback_error = restore(executable, Path::new(&backup), listener);
+    warn!("Failed to go back to the previous binary: {rollback_error}");
+    error
+}
+
+/// Moves the backup in place of the executable and executes it, only returns if that failed
+fn restore(executable: &Path, backup: &Path, listener: Option<&TcpListener>) -> io::Error {
+    if let Err(e) = fs::rename(backup, executable) {
+        return e;
+    }
+    let mut command = Command::new(executable);
+    command.env_remove(ROLLBACK_ENV);
+    exec(command, listener)
+}
+
+/// Executes `command` with the arguments of this process in place of it, only returns if that
+/// failed
+#[cfg(unix)]
+fn exec(mut command: Command, listener: Option<&TcpListener>) -> io::Error {
+    use super::service;
+    use std::os::unix::process::CommandExt;
+
+    command.args(env::args_os().skip(1));
+    match listener {
+        Some(listener) => {
+            if let Err(e) = service::pass_listener(&mut command, listener) {
+                return e;
+            }
+        }
+        None => {
+            command.env_remove("LISTEN_PID").env_remove("LISTEN_FDS");
+        }
+    }
+    command.exec()
+}
+
+#[cfg(not(unix))]
+fn exec(_command: Command, _listener: Option<&TcpListener>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Updates are only supported on Unix",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("ev3-runner 1.3.2\n"), Some("1.3.2"));
+        assert_eq!(parse_version("cargo-ev3 1.3.2\n"), None);
+        assert_eq!(parse_version("ev3-runner \n"), None);
+        assert_eq!(parse_version("ev3-runner 1.3.2 extra"), None);
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(
+            with_suffix(Path::new("/usr/bin/ev3-runner"), "old"),
+            Path::new("/usr/bin/ev3-runner.old")
+        );
+    }
+}
@@ -1,8 +1,8 @@
 mod validate_path;
 
-use super::{ClientHandler, handler::HandlerError};
-use crate::protocol::{MatchStatus, PathStatus, Request, Validation};
-use std::path::PathBuf;
+use super::{ClientHandler, Permission, handler::HandlerError, hash_index::STATE_DIR};
+use crate::protocol::{AuthStatus, MatchStatus, PathStatus, Request, SpaceStatus, Validation};
+use std::{path::PathBuf, time::Instant};
 use tracing::{debug, warn};
 use validate_path::validate_path;
 
@@ -11,18 +11,53 @@ impl ClientHandler {
         &mut self,
         req: &Request,
     ) -> Result<(Validation, PathBuf), HandlerError> {
-        let mut validation = Validation::default();
-
-        if req.password != self.password {
-            self.transport.encode_and_write(validation)?;
-            debug!("Passwords did not match!");
-            return Err(HandlerError::PasswordsDontMatch);
-        } else {
-            validation.password = MatchStatus::Match;
-            debug!("Passwords matched!");
+        let mut validation = Validation {
+            password: self.authenticate(
+                req.user.as_ref(),
+                &req.password,
+                Permission::required(&req.action),
+            ),
+            ..Validation::default()
+        };
+        match validation.password {
+            AuthStatus::Match => debug!("Passwords matched!"),
+            AuthStatus::Mismatch => {
+                self.send_validation(validation)?;
+                debug!("Passwords did not match!");
+                return Err(HandlerError::PasswordsDontMatch);
+            }
+            AuthStatus::LockedOut { .. } => {
+                self.send_validation(validation)?;
+                return Err(HandlerError::LockedOut);
+            }
+            AuthStatus::Forbidden => {
+                self.send_validation(validation)?;
+                let user = req.user.clone().unwrap_or_default();
+                warn!("Account {user} isn't allowed to {:?}", req.action);
+                return Err(HandlerError::Forbidden(user));
+            }
         }
 
-        let (safe_path, path_status) = match validate_path(&req.path) {
+        if !req.action.uses_file() {
+            self.send_validation(validation)?;
+            return Ok((validation, PathBuf::new()));
+        }
+
+        let root = &self.root;
+        let state_dir = self.settings.root.join(STATE_DIR);
+        let checked_path = validate_path(&req.path, root).and_then(|path| {
+            let path = root.join(path);
+            if path.starts_with(&state_dir) {
+                return Err(PathStatus::Reserved);
+            }
+
+            if req.action.needs_file() && !path.is_file() {
+                return Err(PathStatus::NotFound);
+            }
+            Ok(path)
+        });
+
+        let (safe_path, path_status) = match checked_path {
             Ok(sp) => {
                 debug!("Path is valid");
                 (sp, PathStatus::Valid)
@@ -30,15 +65,92 @@ impl ClientHandler {
             Err(e) => {
                 warn!("Path is not valid: {e}");
                 validation.path = e;
-                self.transport.encode_and_write(validation)?;
+                self.send_validation(validation)?;
                 return Err(e.into());
             }
         };
         validation.path = path_status;
 
-        validation.hash = Self::check_hash(&req.path, req.hash)?;
-        self.transport.encode_and_write(validation)?;
+        if req.action.uploads() {
+            validation.hash = self.check_hash(&safe_path, req.hash)?;
+            let uploaded = validation.hash == MatchStatus::Mismatch;
+            validation.space = match req.size {
+                None if uploaded || self.settings.storage.has_quota() => SpaceStatus::SizeUnknown,
+                Some(size) if uploaded => self.check_space(&safe_path, size)?,
+                _ => SpaceStatus::Available,
+            };
+        }
+        self.send_validation(validation)?;
+        if validation.space != SpaceStatus::Available {
+            warn!("Refusing the upload: {}", validation.space);
+            return Err(HandlerError::UploadRefused(validation.space));
+        }
 
         Ok((validation, safe_path))
     }
+
+    fn send_validation(&mut self, validation: Validation) -> Result<(), HandlerError> {
+        self.audit.validation(&validation);
+        self.transport.encode_and_write(validation)?;
+        Ok(())
+    }
+
+    /// Checks the password and whether the account has the `needed` permission, unless too
+    /// many wrong passwords came from the client's address lately
+    pub(super) fn authenticate(
+        &mut self,
+        user: Option<&String>,
+        password: &[u8; 32],
+        needed: Option<Permission>,
+    ) -> AuthStatus {
+        let ip = self.peer.ip();
+        let now = Instant::now();
+        let mut failed_logins = self
+            .settings
+            .failed_logins
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(remaining) = failed_logins.blocked(ip, now) {
+            warn!("Refusing {ip}, it is locked out for another {remaining:?}");
+            return AuthStatus::LockedOut {
+                retry_after_secs: remaining.as_secs_f64().ceil() as u64,
+            };
+        }
+
+        // Without accounts only the server password is accepted, with accounts only them
+        let account = match user {
+            None if self.settings.accounts.is_empty() => None,
+            Some(user) => match self.settings.accounts.get(user) {
+                Some(account) => Some(account),
+                None => {
+                    warn!("Unknown account {user}");
+                    failed_logins.failure(ip, now);
+                    return AuthStatus::Mismatch;
+                }
+            },
+            None => {
+                warn!("Client didn't log in with an account");
+                failed_logins.failure(ip, now);
+                return AuthStatus::Mismatch;
+            }
+        };
+
+        let expected = account.map_or(&self.settings.password, |account| &account.password);
+        if password != expected {
+            failed_logins.failure(ip, now);
+            return AuthStatus::Mismatch;
+        }
+        failed_logins.success(ip);
+
+        let Some(account) = account else {
+            return AuthStatus::Match;
+        };
+        if needed.is_some_and(|permission| !account.may(permission)) {
+            return AuthStatus::Forbidden;
+        }
+        debug!("Logged in as {}", account.name);
+        self.root = account.root.clone();
+        AuthStatus::Match
+    }
 }
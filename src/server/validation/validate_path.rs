@@ -1,5 +1,4 @@
 use super::PathStatus;
-use std::env;
 use std::path::{Component, Path, PathBuf};
 use tracing::info;
 
@@ -10,8 +9,9 @@ use tracing::info;
 /// 2. The path doesn't contain parent directory references (..)
 /// 3. The resolved path stays within the working directory
 ///
+/// `working_dir` has to be canonical.
 /// Returns the sanitized relative path if valid, or an error describing why it's invalid.
-pub fn validate_path(path: &Path) -> Result<PathBuf, PathStatus> {
+pub fn validate_path(path: &Path, working_dir: &Path) -> Result<PathBuf, PathStatus> {
     // Reject absolute paths immediately
     if path.is_absolute() {
         return Err(PathStatus::AbsolutePath);
@@ -22,9 +22,7 @@ pub fn validate_path(path: &Path) -> Result<PathBuf, PathStatus> {
         return Err(PathStatus::InvalidComponents);
     }
 
-    let working_dir = env::current_dir().map_err(|_| PathStatus::CanonicalizationFailed)?;
-
-    let safe_path = resolve_and_validate(path, &working_dir)?;
+    let safe_path = resolve_and_validate(path, working_dir)?;
 
     info!(
         "Validated path: {} -> {}",
@@ -85,6 +83,11 @@ fn canonicalize_nonexistent(path: &Path, working_dir: &Path) -> Result<PathBuf,
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+
+    fn validate_path(path: &Path) -> Result<PathBuf, PathStatus> {
+        super::validate_path(path, &env::current_dir().unwrap())
+    }
 
     #[test]
     fn test_rejects_absolute_paths() {
@@ -1,26 +1,24 @@
 use crate::VERSION;
-use crate::protocol::{VersionHeader, VersionResponse, VersionStatus};
+use crate::protocol::{HashAlgorithms, VersionResponse, VersionStatus};
 use crate::server::handler::{ClientHandler, HandlerError};
 use tracing::{debug, warn};
 
 impl ClientHandler {
-    pub(super) fn check_version(&mut self) -> Result<(), HandlerError> {
-        let version_header = self.transport.read_and_decode::<VersionHeader>()?;
-
+    pub(super) fn check_version(&mut self, client_version: &str) -> Result<(), HandlerError> {
         let mut version_response = VersionResponse(VersionStatus::Match);
-        if version_header.0 != VERSION {
+        if client_version != VERSION {
             version_response = VersionResponse(VersionStatus::Mismatch(VERSION.to_owned()));
             self.transport.encode_and_write(version_response)?;
-            warn!(
-                "Client version ({}) does not match server version ({VERSION})",
-                version_header.0
-            );
+            warn!("Client version ({client_version}) does not match server version ({VERSION})");
             return Err(HandlerError::VersionMismatch(VERSION.to_owned()));
         } else {
             self.transport.encode_and_write(version_response)?;
             debug!("No version mismatch");
         };
 
+        self.transport
+            .encode_and_write(HashAlgorithms(self.settings.hash_algorithms.clone()))?;
+
         Ok(())
     }
 }
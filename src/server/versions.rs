@@ -0,0 +1,331 @@
+use crate::{
+    protocol::{Digest, FileVersion},
+    server::hash_index::STATE_DIR,
+};
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+
+const VERSIONS_DIR: &str = "versions";
+
+/// Earlier versions of deployed files, kept as
+/// `<root>/.ev3-runner/versions/<path>/<deployed_at>-<algorithm>-<hash>`
+#[derive(Debug)]
+pub(super) struct Versions {
+    root: PathBuf,
+    /// Versions kept per file, 0 keeps none
+    keep: usize,
+}
+
+/// A kept version and the file it is stored in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Kept {
+    file: PathBuf,
+    /// Seconds since the Unix epoch
+    deployed_at: u64,
+    hash: Digest,
+}
+
+impl Versions {
+    /// Versions of the files under the canonical `root`
+    pub(super) fn new(root: &Path, keep: usize) -> Self {
+        Self {
+            root: root.to_owned(),
+            keep,
+        }
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.keep > 0
+    }
+
+    /// Keeps the file at the absolute `path`, whose content has `hash`, and drops the oldest
+    /// versions beyond the limit. The file has to be replaced by a new one, not overwritten,
+    /// as the kept version may share its inode.
+    pub(super) fn keep(&self, path: &Path, hash: Digest) -> io::Result<()> {
+        self.store(path, hash)?;
+        self.prune(path)
+    }
+
+    /// Kept versions of the file at the absolute `path`, newest first
+    pub(super) fn list(&self, path: &Path) -> io::Result<Vec<Kept>> {
+        let entries = match fs::read_dir(self.dir(path)?) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut kept = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Some((deployed_at, hash)) = entry.file_name().to_str().and_then(parse_file_name)
+            else {
+                debug!(
+                    "Ignoring {} among the kept versions",
+                    entry.path().display()
+                );
+                continue;
+            };
+            kept.push(Kept {
+                file: entry.path(),
+                deployed_at,
+                hash,
+            });
+        }
+
+        kept.sort_by(|a, b| (b.deployed_at, &b.file).cmp(&(a.deployed_at, &a.file)));
+        Ok(kept)
+    }
+
+    /// Replaces the file at the absolute `path` with the kept version in one step, keeping the
+    /// file it replaces, whose content has `current`, unless it was deleted
+    pub(super) fn restore(
+        &self,
+        kept: &Kept,
+        path: &Path,
+        current: Option<Digest>,
+    ) -> io::Result<()> {
+        if let Some(hash) = current {
+            self.store(path, hash)?;
+        }
+
+        fs::rename(&kept.file, path)?;
+        // Deployed again now, which is when it will have been replaced once it is kept again
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(SystemTime::now())?;
+
+        // Only now, the restored version could have been the oldest one
+        self.prune(path)
+    }
+
+    /// Links the file into the versions of `path`, or copies it where links aren't supported
+    fn store(&self, path: &Path, hash: Digest) -> io::Result<()> {
+        let dir = self.dir(path)?;
+        fs::create_dir_all(&dir)?;
+
+        let deployed_at = seconds_since_epoch(fs::metadata(path)?.modified()?);
+        let file = dir.join(file_name(deployed_at, hash));
+        if file.exists() {
+            return Ok(());
+        }
+        if let Err(e) = fs::hard_link(path, &file) {
+            debug!("Failed to link {}, copying it: {e}", path.display());
+            fs::copy(path, &file)?;
+        }
+        debug!("Kept {} as {}", path.display(), file.display());
+        Ok(())
+    }
+
+    fn prune(&self, path: &Path) -> io::Result<()> {
+        for kept in self.list(path)?.iter().skip(self.keep) {
+            debug!("Dropping {}", kept.file.display());
+            if let Err(e) = fs::remove_file(&kept.file) {
+                warn!("Failed to drop {}: {e}", kept.file.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory the versions of the file or directory at the absolute `path` are kept in
+    pub(super) fn dir(&self, path: &Path) -> io::Result<PathBuf> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is outside of the server root", path.display()),
+            )
+        })?;
+        Ok(self.root.join(STATE_DIR).join(VERSIONS_DIR).join(relative))
+    }
+}
+
+impl Kept {
+    pub(super) fn hash(&self) -> Digest {
+        self.hash
+    }
+
+    pub(super) fn version(&self, now: SystemTime) -> io::Result<FileVersion> {
+        Ok(version(
+            self.hash,
+            fs::metadata(&self.file)?.len(),
+            self.deployed_at,
+            now,
+        ))
+    }
+}
+
+/// Version of the deployed file at `path`, whose content has `hash`
+pub(super) fn deployed(path: &Path, hash: Digest, now: SystemTime) -> io::Result<FileVersion> {
+    let metadata = fs::metadata(path)?;
+    Ok(version(
+        hash,
+        metadata.len(),
+        seconds_since_epoch(metadata.modified()?),
+        now,
+    ))
+}
+
+fn version(hash: Digest, size: u64, deployed_at: u64, now: SystemTime) -> FileVersion {
+    FileVersion {
+        hash,
+        size,
+        deployed_at,
+        age_secs: seconds_since_epoch(now).saturating_sub(deployed_at),
+    }
+}
+
+/// The newest kept version, or the only one whose hash starts with `prefix`, which may
+/// include the algorithm like `sha256:ab12`
+pub(super) fn select<'a>(kept: &'a [Kept], prefix: Option<&str>) -> io::Result<&'a Kept> {
+    let Some(prefix) = prefix else {
+        return kept
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No earlier version is kept"));
+    };
+
+    let prefix = prefix.to_ascii_lowercase();
+    if prefix.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The hash to roll back to is empty",
+        ));
+    }
+    let matches = |kept: &&Kept| {
+        let hash = kept.hash.to_string();
+        let hex = hash.split_once(':').map_or(hash.as_str(), |(_, hex)| hex);
+        if prefix.contains(':') {
+            hash.starts_with(&prefix)
+        } else {
+            hex.starts_with(&prefix)
+        }
+    };
+
+    let mut found = kept.iter().filter(matches);
+    match (found.next(), found.next()) {
+        (Some(kept), None) => Ok(kept),
+        (Some(_), Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Several kept versions have a hash starting with {prefix}"),
+        )),
+        (None, _) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No kept version has a hash starting with {prefix}"),
+        )),
+    }
+}
+
+fn file_name(deployed_at: u64, hash: Digest) -> String {
+    format!("{deployed_at}-{}", hash.to_string().replacen(':', "-", 1))
+}
+
+fn parse_file_name(name: &str) -> Option<(u64, Digest)> {
+    let mut parts = name.splitn(3, '-');
+    let deployed_at = parts.next()?.parse().ok()?;
+    let hash = match (parts.next()?, parts.next()?) {
+        ("xxhash", hex) if hex.len() == 16 => Digest::XxHash64(u64::from_str_radix(hex, 16).ok()?),
+        ("sha256", hex) => Digest::Sha256(decode_hex(hex)?),
+        ("blake3", hex) => Digest::Blake3(decode_hex(hex)?),
+        _ => return None,
+    };
+    Some((deployed_at, hash))
+}
+
+fn decode_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, i) in bytes.iter_mut().zip((0..64).step_by(2)) {
+        *byte = u8::from_str_radix(&hex[i..i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_name_round_trip() {
+        for hash in [
+            Digest::XxHash64(0xfeed),
+            Digest::Sha256([0xab; 32]),
+            Digest::Blake3([7; 32]),
+        ] {
+            assert_eq!(
+                parse_file_name(&file_name(1_700_000_000, hash)),
+                Some((1_700_000_000, hash))
+            );
+        }
+        assert_eq!(parse_file_name("1700000000-md5-abcd"), None);
+        assert_eq!(parse_file_name("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_select() {
+        let kept = |deployed_at, byte| Kept {
+            file: PathBuf::new(),
+            deployed_at,
+            hash: Digest::Sha256([byte; 32]),
+        };
+        let versions = [kept(3, 0xab), kept(2, 0xac), kept(1, 0x12)];
+
+        assert_eq!(select(&versions, None).unwrap(), &versions[0]);
+        assert_eq!(select(&versions, Some("12")).unwrap(), &versions[2]);
+        assert_eq!(select(&versions, Some("SHA256:AC")).unwrap(), &versions[1]);
+        assert!(select(&versions, Some("a")).is_err());
+        assert!(select(&versions, Some("blake3:ab")).is_err());
+        assert!(select(&versions, Some("")).is_err());
+        assert!(select(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_keep_prune_and_restore() {
+        let tmp = TempDir::new("versions");
+        let root = tmp.path();
+        let path = root.join("robot");
+        let versions = Versions::new(root, 2);
+
+        for (n, content) in ["one", "two", "three"].into_iter().enumerate() {
+            fs::write(&path, content).unwrap();
+            let deployed_at = UNIX_EPOCH + Duration::from_secs(1_000 + n as u64);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(deployed_at)
+                .unwrap();
+            versions.keep(&path, Digest::XxHash64(n as u64)).unwrap();
+            fs::remove_file(&path).unwrap();
+        }
+
+        let kept = versions.list(&path).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].hash, Digest::XxHash64(2));
+        assert_eq!(fs::read_to_string(&kept[1].file).unwrap(), "two");
+
+        fs::write(&path, "four").unwrap();
+        versions
+            .restore(&kept[1], &path, Some(Digest::XxHash64(3)))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        let kept = versions.list(&path).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].hash, Digest::XxHash64(3));
+    }
+}
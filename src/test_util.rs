@@ -0,0 +1,30 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Directory for the files of one test, removed again when the test ends, even if it fails
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty `ev3-runner-<name>-<pid>` in the system's temp directory
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("ev3-runner-{name}-{}", process::id()));
+        // Left over if an earlier run was killed
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir.canonicalize().unwrap())
+    }
+
+    /// Canonical path of the directory
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,38 +1,165 @@
+#[cfg(feature = "async")]
+mod async_transport;
 mod file_transfer;
 mod framed;
 mod process_stream;
 mod stream_framer;
 
 use bincode::error::{DecodeError, EncodeError};
+use socket2::{SockRef, TcpKeepalive};
 use std::io::{Error, Write};
-use std::net::{Shutdown, TcpStream};
-use std::ops::{Deref, DerefMut};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async")]
+pub use async_transport::AsyncTransport;
+pub use file_transfer::TransferProgress;
+
+pub(crate) use framed::MAX_MESSAGE_SIZE_LIMIT;
+pub(crate) use stream_framer::CHUNK_SIZE;
 
 #[derive(Debug, thiserror::Error)]
 pub enum TransportError {
     #[error("Io error: {0}")]
-    Io(#[from] Error),
+    Io(Error),
     #[error("Decode error: {0}")]
     Decode(#[from] DecodeError),
     #[error("Encode error: {0}")]
     Encode(#[from] EncodeError),
+    #[error("Message of {size} bytes is larger than the limit of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+    #[error("File chunk of {size} bytes is larger than the limit of {max} bytes")]
+    ChunkTooLarge { size: usize, max: usize },
+    #[error("File is larger than the announced {max} bytes")]
+    FileTooLarge { max: u64 },
+    #[error("Malformed message: {unread} of {size} bytes were left over after decoding")]
+    TrailingBytes { size: usize, unread: usize },
+}
+
+impl From<Error> for TransportError {
+    /// Unwraps transport errors that had to pass through an `io::Read`, like `ChunkTooLarge`
+    fn from(e: Error) -> Self {
+        if !e
+            .get_ref()
+            .is_some_and(|inner| inner.is::<TransportError>())
+        {
+            return TransportError::Io(e);
+        }
+        *e.into_inner()
+            .and_then(|inner| inner.downcast().ok())
+            .expect("checked that the error is a TransportError")
+    }
+}
+
+/// Largest message and file chunk accepted from the peer, so a bogus length prefix can't make
+/// us allocate up to 4 GiB, and largest zstd window, so a frame header can't make us reserve
+/// 128 MiB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) max_message_size: usize,
+    pub(crate) max_chunk_size: usize,
+    /// Base 2 logarithm of the window size in bytes
+    pub(crate) max_window_log: u32,
+}
+
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+pub(crate) const DEFAULT_MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// 8 MiB, the largest window compression level 19 uses
+pub(crate) const DEFAULT_MAX_WINDOW_LOG: u32 = 23;
+/// Window logs zstd supports, 27 is the largest any compression level uses
+pub(crate) const WINDOW_LOG_RANGE: RangeInclusive<u32> = 10..=27;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            max_window_log: DEFAULT_MAX_WINDOW_LOG,
+        }
+    }
 }
 
 pub struct Transport {
     pub stream: TcpStream,
+    limits: Limits,
+    /// Reading a message fails once this passes, see `set_deadline`
+    deadline: Option<Instant>,
 }
 
 impl Transport {
     pub fn new(stream: TcpStream) -> Self {
         stream.set_nodelay(true).unwrap();
-        Self { stream }
+        Self {
+            stream,
+            limits: Limits::default(),
+            deadline: None,
+        }
     }
 
-    pub fn connect(addr: &str) -> Result<Self, TransportError> {
-        let stream = TcpStream::connect(addr)?;
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Makes `read_and_decode` fail once `deadline` passes, no matter how slowly the peer
+    /// trickles in data. Clearing it leaves the read timeout as it was last set.
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Sends TCP keepalive probes after the connection was idle for `idle`, so a peer that
+    /// disappeared without closing the connection is noticed
+    pub fn set_keepalive(&self, idle: Option<Duration>) -> Result<(), Error> {
+        set_keepalive(&self.stream, idle)
+    }
+
+    pub fn connect(addr: &str, timeout: Option<Duration>) -> Result<Self, TransportError> {
+        let stream = match timeout {
+            Some(timeout) => Self::connect_timeout(addr, timeout)?,
+            None => TcpStream::connect(addr)?,
+        };
         stream.set_nodelay(true)?;
-        Ok(Self { stream })
+        Ok(Self::new(stream))
     }
+
+    /// Tries every address `addr` resolves to until one accepts the connection in time
+    fn connect_timeout(addr: &str, timeout: Duration) -> Result<TcpStream, Error> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Address didn't resolve to anything",
+            )
+        }))
+    }
+}
+
+pub(crate) fn set_keepalive<'s, S>(socket: &'s S, idle: Option<Duration>) -> Result<(), Error>
+where
+    SockRef<'s>: From<&'s S>,
+{
+    let socket = SockRef::from(socket);
+    let Some(idle) = idle else {
+        return socket.set_keepalive(false);
+    };
+
+    let keepalive = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        windows
+    ))]
+    let keepalive = keepalive.with_interval(idle);
+    socket.set_tcp_keepalive(&keepalive)
 }
 
 impl Deref for Transport {
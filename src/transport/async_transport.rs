@@ -0,0 +1,250 @@
+use super::{
+    Limits, TransferProgress, TransportError,
+    file_transfer::zstd_encoder,
+    framed::{LENGTH_PREFIX_SIZE, decode_frame, encode_frame, message_size},
+    stream_framer::{CHUNK_SIZE, FRAME_LENGTH_SIZE},
+};
+use crate::protocol::Compression;
+use bincode::{de::Decode, enc::Encode};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+use tracing::{debug, warn};
+
+/// Async counterpart of `Transport`, speaking the same protocol
+pub struct AsyncTransport {
+    pub stream: TcpStream,
+    limits: Limits,
+}
+
+impl AsyncTransport {
+    pub fn new(stream: TcpStream) -> Result<Self, TransportError> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            limits: Limits::default(),
+        })
+    }
+
+    /// See `Transport::set_keepalive`
+    pub fn set_keepalive(&self, idle: Option<Duration>) -> Result<(), io::Error> {
+        super::set_keepalive(&self.stream, idle)
+    }
+
+    pub async fn connect(addr: &str, timeout: Option<Duration>) -> Result<Self, TransportError> {
+        let connect = TcpStream::connect(addr);
+        let stream = match timeout {
+            Some(timeout) => time::timeout(timeout, connect)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Connecting timed out"))??,
+            None => connect.await?,
+        };
+        Self::new(stream)
+    }
+
+    pub async fn encode_and_write<T>(&mut self, data: T) -> Result<(), TransportError>
+    where
+        T: Encode,
+    {
+        let frame = encode_frame(data)?;
+        self.stream
+            .write_all(&frame)
+            .await
+            .inspect_err(|e| warn!("Failed to write the data to the stream: {e}"))?;
+        Ok(())
+    }
+
+    pub async fn read_and_decode<T>(&mut self) -> Result<T, TransportError>
+    where
+        T: Decode<()>,
+    {
+        let mut len = [0u8; LENGTH_PREFIX_SIZE];
+        self.stream
+            .read_exact(&mut len)
+            .await
+            .inspect_err(|e| warn!("Failed to read the data length from the socket: {e}"))?;
+
+        let mut buf = vec![0u8; message_size(len, &self.limits)?];
+        self.stream
+            .read_exact(&mut buf)
+            .await
+            .inspect_err(|e| warn!("Failed to read the data from the stream: {e}"))?;
+
+        decode_frame(&buf, &self.limits)
+    }
+
+    /// Sends the file in the same chunked format as `Transport::upload_file`, calling
+    /// `progress` after every read from it
+    pub async fn upload_file<R, P>(
+        &mut self,
+        file: &mut R,
+        compression: Compression,
+        mut progress: P,
+    ) -> Result<TransferProgress, TransportError>
+    where
+        R: AsyncRead + Unpin,
+        P: FnMut(TransferProgress),
+    {
+        let instant = Instant::now();
+
+        let mut encoder = match compression {
+            Compression::Zstd { level, workers } => Some(zstd_encoder(Vec::new(), level, workers)?),
+            Compression::None => None,
+        };
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut pending = Vec::new();
+        let mut transferred = TransferProgress::default();
+        loop {
+            let n = file
+                .read(&mut buf)
+                .await
+                .inspect_err(|e| warn!("Failed to read the file: {e}"))?;
+            if n == 0 {
+                break;
+            }
+            transferred.read += n as u64;
+
+            match &mut encoder {
+                Some(encoder) => {
+                    encoder.write_all(&buf[..n])?;
+                    pending.append(encoder.get_mut());
+                }
+                None => pending.extend_from_slice(&buf[..n]),
+            }
+            let started = Instant::now();
+            transferred.written += self.write_chunks(&mut pending, false).await?;
+            transferred.wire_time += started.elapsed();
+            progress(transferred);
+        }
+
+        if let Some(encoder) = encoder {
+            let rest = encoder
+                .finish()
+                .inspect_err(|e| warn!("Failed to finish the zstd encoder: {e}"))?;
+            pending.extend_from_slice(&rest);
+        }
+        let started = Instant::now();
+        transferred.written += self.write_chunks(&mut pending, true).await?;
+        self.stream.write_all(&0u32.to_le_bytes()).await?;
+        self.stream
+            .flush()
+            .await
+            .inspect_err(|e| warn!("Failed to flush the stream: {e}"))?;
+        transferred.wire_time += started.elapsed();
+        progress(transferred);
+
+        debug!(
+            "Sending file: {} bytes ({} on the wire), took {:?}",
+            transferred.read,
+            transferred.written,
+            instant.elapsed()
+        );
+
+        Ok(transferred)
+    }
+
+    /// Writes the full chunks of `pending`, or everything if `all` is set, and returns the
+    /// number of bytes written
+    async fn write_chunks(&mut self, pending: &mut Vec<u8>, all: bool) -> Result<u64, io::Error> {
+        let mut written = 0;
+        while pending.len() >= CHUNK_SIZE || (all && !pending.is_empty()) {
+            let n = pending.len().min(CHUNK_SIZE);
+
+            let mut frame = Vec::with_capacity(FRAME_LENGTH_SIZE + n);
+            frame.extend_from_slice(&(n as u32).to_le_bytes());
+            frame.extend_from_slice(&pending[..n]);
+            self.stream
+                .write_all(&frame)
+                .await
+                .inspect_err(|e| warn!("Failed to write a chunk to the stream: {e}"))?;
+
+            pending.drain(..n);
+            written += n as u64;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+    use std::{net::TcpListener, thread};
+    use tokio::runtime;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Runs `blocking` on the accepted end and `nonblocking` on the connecting end of a socket
+    fn connected<T: Send + 'static>(
+        blocking: impl FnOnce(Transport) -> T + Send + 'static,
+        nonblocking: impl AsyncFnOnce(AsyncTransport),
+    ) -> T {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || blocking(Transport::new(listener.accept().unwrap().0)));
+
+        block_on(async {
+            let transport = AsyncTransport::connect(&addr, None).await.unwrap();
+            nonblocking(transport).await;
+        });
+        server.join().unwrap()
+    }
+
+    const COMPRESSIONS: [Compression; 3] = [
+        Compression::None,
+        Compression::Zstd {
+            level: 3,
+            workers: 0,
+        },
+        Compression::Zstd {
+            level: 19,
+            workers: 2,
+        },
+    ];
+
+    fn file_content() -> Vec<u8> {
+        (0..200_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_async_upload_is_wire_compatible() {
+        for compression in COMPRESSIONS {
+            let received = connected(
+                move |mut transport| {
+                    let request: String = transport.read_and_decode().unwrap();
+                    let mut file = Vec::new();
+                    transport
+                        .download_file(&mut file, compression, u64::MAX)
+                        .unwrap();
+                    (request, file)
+                },
+                async move |mut transport| {
+                    transport.encode_and_write("upload").await.unwrap();
+                    let content = file_content();
+                    let transferred = transport
+                        .upload_file(&mut content.as_slice(), compression, |_| {})
+                        .await
+                        .unwrap();
+                    assert_eq!(transferred.read, content.len() as u64);
+                },
+            );
+
+            assert_eq!(received, ("upload".to_owned(), file_content()));
+        }
+    }
+}
@@ -1,40 +1,77 @@
 use super::{Transport, TransportError, stream_framer::StreamFramer};
+use crate::protocol::Compression;
 use std::{
+    cell::Cell,
     io::{self, BufReader, BufWriter, Read, Write},
-    time::Instant,
+    time::{Duration, Instant},
 };
 use tracing::{debug, warn};
 use zstd::{Decoder, Encoder};
 
+/// Progress of a file upload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Bytes read from the file
+    pub read: u64,
+    /// Bytes written to the connection, fewer than `read` if compression pays off
+    pub written: u64,
+    /// Time spent writing to the connection, without reading and compressing the file
+    pub wire_time: Duration,
+}
+
 impl Transport {
     pub const FILE_TRANSFER_BUFFER: usize = 512 * 1024;
-    const ENCODER_LEVEL: i32 = 3;
 
-    pub fn upload_file<R>(
+    /// Sends the file, calling `progress` after every read from it.
+    ///
+    /// Returns the number of bytes read from the file and written to the connection.
+    pub fn upload_file<R, P>(
         &mut self,
         file: &mut R,
-        use_compression: bool,
-    ) -> Result<(), TransportError>
+        compression: Compression,
+        mut progress: P,
+    ) -> Result<TransferProgress, TransportError>
     where
         R: Read,
+        P: FnMut(TransferProgress),
     {
         let instant = Instant::now();
 
-        let mut buf_writer = BufWriter::with_capacity(Self::FILE_TRANSFER_BUFFER, &mut self.stream);
-        let mut writer = StreamFramer::streaming_writer(&mut buf_writer);
+        let written = Cell::new(0);
+        let wire_time = Cell::new(Duration::ZERO);
+        let mut buf_writer = BufWriter::with_capacity(
+            Self::FILE_TRANSFER_BUFFER,
+            TimedWriter {
+                inner: &mut self.stream,
+                elapsed: &wire_time,
+            },
+        );
+        let mut writer = CountingWriter {
+            inner: StreamFramer::streaming_writer(&mut buf_writer),
+            written: &written,
+        };
+        let mut reader = ProgressReader {
+            inner: file,
+            read: 0,
+            written: &written,
+            wire_time: &wire_time,
+            progress: &mut progress,
+        };
 
-        let bytes = if use_compression {
-            let mut enocder = Encoder::new(&mut writer, Self::ENCODER_LEVEL)
-                .inspect_err(|e| warn!("Failed to create new zstd encoder: {e}"))?;
-            let bytes = io::copy(file, &mut enocder);
+        if let Compression::Zstd { level, workers } = compression {
+            let mut enocder = zstd_encoder(&mut writer, level, workers)?;
+            io::copy(&mut reader, &mut enocder).inspect_err(|e| {
+                warn!("Failed to copy data between the file and the tcp stream: {e}")
+            })?;
             enocder
                 .finish()
                 .inspect_err(|e| warn!("Failed to finish the zstd encoder: {e}"))?;
-            bytes
         } else {
-            io::copy(file, &mut writer)
+            io::copy(&mut reader, &mut writer).inspect_err(|e| {
+                warn!("Failed to copy data between the file and the tcp stream: {e}")
+            })?;
         }
-        .inspect_err(|e| warn!("Failed to copy data between the file and the tcp stream: {e}"))?;
+        let read = reader.read;
 
         writer
             .flush()
@@ -44,32 +81,47 @@ impl Transport {
             .flush()
             .inspect_err(|e| warn!("Failed to flush bufwriter: {e}"))?;
 
-        debug!("Sending file: {bytes} bytes, took {:?}", instant.elapsed());
+        let transferred = TransferProgress {
+            read,
+            written: written.get(),
+            wire_time: wire_time.get(),
+        };
+        progress(transferred);
+
+        debug!(
+            "Sending file: {read} bytes ({} on the wire), took {:?}",
+            transferred.written,
+            instant.elapsed()
+        );
 
-        Ok(())
+        Ok(transferred)
     }
 
+    /// Receives a file of at most `max_size` bytes, returns the number of bytes written to
+    /// `file`
     pub fn download_file<W>(
         &mut self,
         file: &mut W,
-        use_compression: bool,
-    ) -> Result<(), TransportError>
+        compression: Compression,
+        max_size: u64,
+    ) -> Result<u64, TransportError>
     where
         W: Write,
     {
         let instant = Instant::now();
 
-        let mut reader = StreamFramer::streaming_reader(BufReader::with_capacity(
-            Self::FILE_TRANSFER_BUFFER,
-            &mut self.stream,
-        ));
+        let mut reader = StreamFramer::streaming_reader(
+            BufReader::with_capacity(Self::FILE_TRANSFER_BUFFER, &mut self.stream),
+            self.limits.max_chunk_size,
+        );
 
-        let bytes = if use_compression {
+        let bytes = if compression.is_enabled() {
             let mut decoder = Decoder::new(&mut reader)
                 .inspect_err(|e| warn!("Failed to create new zstd decoder: {e}"))?;
-            io::copy(&mut decoder, file)
+            decoder.window_log_max(self.limits.max_window_log)?;
+            io::copy(&mut SizeLimit::new(decoder, max_size), file)
         } else {
-            io::copy(&mut reader, file)
+            io::copy(&mut SizeLimit::new(&mut reader, max_size), file)
         }
         .inspect_err(|e| warn!("Failed to copy data between the tcp stream and file: {e}"))?;
         file.flush()
@@ -80,6 +132,205 @@ impl Transport {
             instant.elapsed()
         );
 
-        Ok(())
+        Ok(bytes)
+    }
+}
+
+/// zstd encoder compressing on `workers` background threads, or on the calling thread if it is 0
+pub(super) fn zstd_encoder<W: Write>(
+    writer: W,
+    level: i32,
+    workers: u32,
+) -> Result<Encoder<'static, W>, io::Error> {
+    let mut encoder = Encoder::new(writer, level)
+        .inspect_err(|e| warn!("Failed to create new zstd encoder: {e}"))?;
+    if workers > 0 {
+        encoder
+            .multithread(workers)
+            .inspect_err(|e| warn!("Failed to enable multithreaded compression: {e}"))?;
+    }
+    Ok(encoder)
+}
+
+/// Fails with `TransportError::FileTooLarge` wrapped in an `io::Error` once more than `max`
+/// bytes were read
+struct SizeLimit<R> {
+    inner: R,
+    read: u64,
+    max: u64,
+}
+
+impl<R> SizeLimit<R> {
+    fn new(inner: R, max: u64) -> Self {
+        Self {
+            inner,
+            read: 0,
+            max,
+        }
+    }
+}
+
+impl<R: Read> Read for SizeLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read > self.max {
+            warn!(
+                "Refusing the file, it is larger than the announced {} bytes",
+                self.max
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                TransportError::FileTooLarge { max: self.max },
+            ));
+        }
+        Ok(n)
+    }
+}
+
+/// Reports the progress after every read of the file
+struct ProgressReader<'a, R, P> {
+    inner: R,
+    read: u64,
+    written: &'a Cell<u64>,
+    wire_time: &'a Cell<Duration>,
+    progress: &'a mut P,
+}
+
+impl<R: Read, P: FnMut(TransferProgress)> Read for ProgressReader<'_, R, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        (self.progress)(TransferProgress {
+            read: self.read,
+            written: self.written.get(),
+            wire_time: self.wire_time.get(),
+        });
+        Ok(n)
+    }
+}
+
+/// Counts the bytes going into the stream framer
+struct CountingWriter<'a, W> {
+    inner: W,
+    written: &'a Cell<u64>,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.set(self.written.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Adds up the time spent writing to the connection
+struct TimedWriter<'a, W> {
+    inner: W,
+    elapsed: &'a Cell<Duration>,
+}
+
+impl<W> TimedWriter<'_, W> {
+    fn timed<T>(&mut self, f: impl FnOnce(&mut W) -> T) -> T {
+        let started = Instant::now();
+        let result = f(&mut self.inner);
+        self.elapsed.set(self.elapsed.get() + started.elapsed());
+        result
+    }
+}
+
+impl<W: Write> Write for TimedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.timed(|inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.timed(|inner| inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    /// Uploads `content` from one end of a socket and downloads it on the other
+    fn transfer(
+        content: Vec<u8>,
+        compression: Compression,
+        max_size: u64,
+    ) -> Result<Vec<u8>, TransportError> {
+        receive(compression, max_size, move |transport| {
+            transport
+                .upload_file(&mut content.as_slice(), compression, |_| {})
+                .map(|_| ())
+        })
+    }
+
+    /// Downloads the file `send` uploads from the other end of a socket
+    fn receive(
+        compression: Compression,
+        max_size: u64,
+        send: impl FnOnce(&mut Transport) -> Result<(), TransportError> + Send + 'static,
+    ) -> Result<Vec<u8>, TransportError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut transport = Transport::new(TcpStream::connect(addr).unwrap());
+            // The receiver may hang up early
+            send(&mut transport).ok();
+        });
+
+        let mut transport = Transport::new(listener.accept().unwrap().0);
+        let mut file = Vec::new();
+        let result = transport.download_file(&mut file, compression, max_size);
+        drop(transport);
+        client.join().unwrap();
+        result.map(|_| file)
+    }
+
+    #[test]
+    fn test_file_larger_than_announced_is_refused() {
+        let content = vec![7; 100_000];
+        for compression in [
+            Compression::None,
+            Compression::Zstd {
+                level: 3,
+                workers: 0,
+            },
+        ] {
+            assert_eq!(
+                transfer(content.clone(), compression, 100_000).unwrap(),
+                content
+            );
+            assert!(matches!(
+                transfer(content.clone(), compression, 1_000),
+                Err(TransportError::FileTooLarge { max: 1_000 })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_window_larger_than_limit_is_refused() {
+        let compression = Compression::Zstd {
+            level: 3,
+            workers: 0,
+        };
+        let result = receive(compression, u64::MAX, |transport| {
+            let mut writer = StreamFramer::streaming_writer(&mut transport.stream);
+            let mut encoder = Encoder::new(&mut writer, 3)?;
+            encoder.window_log(27)?;
+            encoder.write_all(&[1; 1_000])?;
+            encoder.finish()?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(TransportError::Io(_))));
     }
 }
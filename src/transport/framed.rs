@@ -1,23 +1,26 @@
-use super::{Transport, TransportError};
-use bincode::{config::standard, de::Decode, enc::Encode};
-use std::io::{Read, Write};
+use super::{Limits, Transport, TransportError};
+use bincode::{config::standard, de::Decode, enc::Encode, error::DecodeError};
+use std::{
+    io::{self, Read, Write},
+    time::Instant,
+};
 use tracing::warn;
 
+/// Size of the big-endian length prefix in front of every message
+pub(super) const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Upper bound for the configurable message size, also the most memory bincode may claim
+/// while decoding a single message
+pub(crate) const MAX_MESSAGE_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
 impl Transport {
     pub fn encode_and_write<T>(&mut self, data: T) -> Result<(), TransportError>
     where
         T: Encode,
     {
-        let encoded = bincode::encode_to_vec(data, standard())
-            .inspect_err(|e| warn!("Failed to encode the data: {e}"))?;
-        let len = encoded.len();
-        let size = (len as u32).to_be_bytes();
-
+        let frame = encode_frame(data)?;
         self.stream
-            .write_all(&size)
-            .inspect_err(|e| warn!("Failed to write the data length to the stream: {e}"))?;
-        self.stream
-            .write_all(&encoded)
+            .write_all(&frame)
             .inspect_err(|e| warn!("Failed to write the data to the stream: {e}"))?;
         Ok(())
     }
@@ -26,19 +29,193 @@ impl Transport {
     where
         T: Decode<()>,
     {
-        let mut len = [0u8; 4];
-        self.stream
-            .read_exact(&mut len)
+        let mut len = [0u8; LENGTH_PREFIX_SIZE];
+        self.read_until_deadline(&mut len)
             .inspect_err(|e| warn!("Failed to read the data length from the socket: {e}"))?;
-        let size = u32::from_be_bytes(len) as usize;
 
-        let mut buf = vec![0u8; size];
-        self.stream
-            .read_exact(&mut buf)
+        let mut buf = vec![0u8; message_size(len, &self.limits)?];
+        self.read_until_deadline(&mut buf)
             .inspect_err(|e| warn!("Failed to read the data from the stream: {e}"))?;
 
-        let (data, _) = bincode::decode_from_slice(&buf, standard())
-            .inspect_err(|e| warn!("Failed to decode the data: {e}"))?;
-        Ok(data)
+        decode_frame(&buf, &self.limits)
+    }
+
+    /// `read_exact` that shrinks the read timeout to what is left until the deadline before
+    /// every read, if there is one
+    fn read_until_deadline(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        let Some(deadline) = self.deadline else {
+            return self.stream.read_exact(buf);
+        };
+
+        while !buf.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The peer didn't finish the handshake in time",
+                ));
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+
+            match self.stream.read(buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => buf = &mut buf[n..],
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Size of the message behind the length prefix, checked before anything is allocated for it
+pub(super) fn message_size(
+    len: [u8; LENGTH_PREFIX_SIZE],
+    limits: &Limits,
+) -> Result<usize, TransportError> {
+    let size = u32::from_be_bytes(len) as usize;
+    if size > limits.max_message_size {
+        warn!(
+            "Refusing message of {size} bytes, the limit is {} bytes",
+            limits.max_message_size
+        );
+        return Err(TransportError::MessageTooLarge {
+            size,
+            max: limits.max_message_size,
+        });
+    }
+    Ok(size)
+}
+
+/// Encodes `data` and puts the length prefix in front of it
+pub(super) fn encode_frame<T: Encode>(data: T) -> Result<Vec<u8>, TransportError> {
+    let encoded = bincode::encode_to_vec(data, standard())
+        .inspect_err(|e| warn!("Failed to encode the data: {e}"))?;
+
+    let mut frame = Vec::with_capacity(LENGTH_PREFIX_SIZE + encoded.len());
+    frame.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&encoded);
+    Ok(frame)
+}
+
+/// Decodes the body of a message, without the length prefix, which has to be used up entirely
+pub(super) fn decode_frame<T: Decode<()>>(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<T, TransportError> {
+    let (data, read) =
+        decode_with_limits(buf, limits).inspect_err(|e| warn!("Failed to decode the data: {e}"))?;
+
+    if read != buf.len() {
+        warn!(
+            "Message of {} bytes had {} bytes left over after decoding",
+            buf.len(),
+            buf.len() - read
+        );
+        return Err(TransportError::TrailingBytes {
+            size: buf.len(),
+            unread: buf.len() - read,
+        });
+    }
+    Ok(data)
+}
+
+/// bincode only takes its decode limit as a constant, so this picks the smallest power of two
+/// that fits the configured message size, capped at `MAX_MESSAGE_SIZE_LIMIT`
+fn decode_with_limits<T: Decode<()>>(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<(T, usize), DecodeError> {
+    match limits.max_message_size.next_power_of_two() {
+        ..=0x1000 => decode_with_limit::<T, 0x1000>(buf),
+        0x2000 => decode_with_limit::<T, 0x2000>(buf),
+        0x4000 => decode_with_limit::<T, 0x4000>(buf),
+        0x8000 => decode_with_limit::<T, 0x8000>(buf),
+        0x1_0000 => decode_with_limit::<T, 0x1_0000>(buf),
+        0x2_0000 => decode_with_limit::<T, 0x2_0000>(buf),
+        0x4_0000 => decode_with_limit::<T, 0x4_0000>(buf),
+        0x8_0000 => decode_with_limit::<T, 0x8_0000>(buf),
+        0x10_0000 => decode_with_limit::<T, 0x10_0000>(buf),
+        0x20_0000 => decode_with_limit::<T, 0x20_0000>(buf),
+        0x40_0000 => decode_with_limit::<T, 0x40_0000>(buf),
+        0x80_0000 => decode_with_limit::<T, 0x80_0000>(buf),
+        _ => decode_with_limit::<T, MAX_MESSAGE_SIZE_LIMIT>(buf),
+    }
+}
+
+/// Same encoding as `standard()`, but decoding fails instead of allocating more than `LIMIT`
+/// for lengths inside the message
+fn decode_with_limit<T: Decode<()>, const LIMIT: usize>(
+    buf: &[u8],
+) -> Result<(T, usize), DecodeError> {
+    bincode::decode_from_slice(buf, standard().with_limit::<LIMIT>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = encode_frame(("path", 42u64)).unwrap();
+        let len = frame[..LENGTH_PREFIX_SIZE].try_into().unwrap();
+        assert_eq!(
+            message_size(len, &Limits::default()).unwrap(),
+            frame.len() - LENGTH_PREFIX_SIZE
+        );
+
+        let data: (String, u64) =
+            decode_frame(&frame[LENGTH_PREFIX_SIZE..], &Limits::default()).unwrap();
+        assert_eq!(data, ("path".to_owned(), 42));
+    }
+
+    #[test]
+    fn test_oversized_message_is_refused() {
+        let limits = Limits {
+            max_message_size: 1024,
+            ..Limits::default()
+        };
+        assert!(matches!(
+            message_size(u32::MAX.to_be_bytes(), &limits),
+            Err(TransportError::MessageTooLarge {
+                size: 0xffff_ffff,
+                max: 1024
+            })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_messages_are_rejected() {
+        let frame = encode_frame(7u8).unwrap();
+        let mut body = frame[LENGTH_PREFIX_SIZE..].to_vec();
+        body.push(0);
+        assert!(matches!(
+            decode_frame::<u8>(&body, &Limits::default()),
+            Err(TransportError::TrailingBytes { size: 2, unread: 1 })
+        ));
+
+        // A string claiming to be far larger than the decode limit
+        let body = bincode::encode_to_vec(u64::MAX, standard()).unwrap();
+        assert!(matches!(
+            decode_frame::<String>(&body, &Limits::default()),
+            Err(TransportError::Decode(DecodeError::LimitExceeded))
+        ));
+
+        // Below the hard cap, but above the configured message size
+        let limits = Limits {
+            max_message_size: 1024,
+            ..Limits::default()
+        };
+        let body = bincode::encode_to_vec(0x10_0000u64, standard()).unwrap();
+        assert!(matches!(
+            decode_frame::<String>(&body, &limits),
+            Err(TransportError::Decode(DecodeError::LimitExceeded))
+        ));
     }
 }
@@ -1,28 +1,33 @@
 use super::{Transport, TransportError};
-use crate::BUFFER_SIZE;
-use std::io::{Read, Write};
+use crate::{
+    BUFFER_SIZE,
+    protocol::{ExitStatus, OutputStream, RunEvent},
+};
+use std::{
+    io::{self, Read},
+    sync::mpsc,
+    thread,
+};
 use tracing::{debug, warn};
 
 impl Transport {
-    pub fn forward_output<R>(&mut self, output: &mut R) -> Result<(), TransportError>
+    /// Forwards stdout and stderr of a program as `RunEvent::Output` messages until both are closed
+    pub fn forward_output<O, E>(&mut self, stdout: O, stderr: E) -> Result<(), TransportError>
     where
-        R: Read,
+        O: Read + Send + 'static,
+        E: Read + Send + 'static,
     {
         let mut bytes = 0usize;
-        let mut buf = [0u8; BUFFER_SIZE];
+        let (sender, receiver) = mpsc::channel();
 
-        loop {
-            let n = output
-                .read(&mut buf)
-                .inspect_err(|e| warn!("Failed to read output of the spawned command: {e}"))?;
+        // The readers aren't joined, they stop as soon as the program closes its output
+        let stderr_sender = sender.clone();
+        thread::spawn(move || read_chunks(stdout, OutputStream::Stdout, sender));
+        thread::spawn(move || read_chunks(stderr, OutputStream::Stderr, stderr_sender));
 
-            if n == 0 {
-                break;
-            }
-            bytes += n;
-
-            self.stream
-                .write_all(&buf[..n])
+        for (stream, chunk) in receiver {
+            bytes += chunk.len();
+            self.encode_and_write(RunEvent::Output(stream, chunk))
                 .inspect_err(|e| warn!("Failed to write output to the stream: {e}"))?;
         }
 
@@ -31,34 +36,32 @@ impl Transport {
         Ok(())
     }
 
-    pub fn receive_output<W>(&mut self, output: &mut W) -> Result<(), TransportError>
-    where
-        W: Write,
-    {
-        let mut bytes = 0usize;
-        let mut buf = [0u8; BUFFER_SIZE];
+    pub fn send_exit_status(&mut self, status: ExitStatus) -> Result<(), TransportError> {
+        self.encode_and_write(RunEvent::Exit(status))
+    }
+}
 
-        loop {
-            let n = self
-                .stream
-                .read(&mut buf)
-                .inspect_err(|e| warn!("Failed to read from the stream: {e}"))?;
+/// Reads `output` until it is closed and sends every chunk to `sender`
+fn read_chunks<R: Read>(
+    mut output: R,
+    stream: OutputStream,
+    sender: mpsc::Sender<(OutputStream, Vec<u8>)>,
+) {
+    let mut buf = [0u8; BUFFER_SIZE];
 
-            if n == 0 {
+    loop {
+        let n = match output.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Failed to read output of the spawned command: {e}");
                 break;
             }
-            bytes += n;
+        };
 
-            output
-                .write_all(&buf[..n])
-                .inspect_err(|e| warn!("Failed to write to the output: {e}"))?;
-            output
-                .flush()
-                .inspect_err(|e| warn!("Failed to flush the output: {e}"))?;
+        if sender.send((stream, buf[..n].to_vec())).is_err() {
+            break;
         }
-
-        debug!("Streamed stream to output: {bytes} bytes");
-
-        Ok(())
     }
 }
@@ -1,18 +1,22 @@
+use super::TransportError;
 use std::io::{self, Read, Write};
+use tracing::warn;
 
-const CHUNK_SIZE: usize = 32 * 1024;
-const FRAME_LENGTH_SIZE: usize = 4;
+pub(crate) const CHUNK_SIZE: usize = 32 * 1024;
+pub(super) const FRAME_LENGTH_SIZE: usize = 4;
 
 /// Stream framing protocol for continuous streaming
 pub struct StreamFramer;
 
 impl StreamFramer {
-    /// Create a streaming reader that yields data as it arrives
-    pub fn streaming_reader<R: Read>(reader: R) -> ChunkedReader<R> {
+    /// Create a streaming reader that yields data as it arrives, refusing chunks larger than
+    /// `max_chunk_size`
+    pub fn streaming_reader<R: Read>(reader: R, max_chunk_size: usize) -> ChunkedReader<R> {
         ChunkedReader {
             inner: reader,
             buffer: Vec::new(),
             done: false,
+            max_chunk_size,
         }
     }
 
@@ -31,6 +35,7 @@ pub struct ChunkedReader<R: Read> {
     inner: R,
     buffer: Vec<u8>,
     done: bool,
+    max_chunk_size: usize,
 }
 
 impl<R: Read> Read for ChunkedReader<R> {
@@ -54,6 +59,7 @@ impl<R: Read> Read for ChunkedReader<R> {
             self.done = true;
             return Ok(0);
         }
+        check_chunk_size(chunk_len, self.max_chunk_size)?;
 
         if chunk_len <= buf.len() {
             self.inner.read_exact(&mut buf[..chunk_len])?;
@@ -70,6 +76,19 @@ impl<R: Read> Read for ChunkedReader<R> {
     }
 }
 
+/// Fails with `TransportError::ChunkTooLarge` wrapped in an `io::Error`, which `TransportError`
+/// unwraps again
+fn check_chunk_size(size: usize, max: usize) -> io::Result<()> {
+    if size > max {
+        warn!("Refusing file chunk of {size} bytes, the limit is {max} bytes");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            TransportError::ChunkTooLarge { size, max },
+        ));
+    }
+    Ok(())
+}
+
 /// Writer adapter that writes data using chunked protocol
 pub struct ChunkedWriter<W: Write> {
     inner: W,
@@ -110,3 +129,40 @@ impl<W: Write> Drop for ChunkedWriter<W> {
         let _ = self.inner.flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_roundtrip() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mut framed = Vec::new();
+        StreamFramer::streaming_writer(&mut framed)
+            .write_all(&data)
+            .unwrap();
+
+        let mut received = Vec::new();
+        StreamFramer::streaming_reader(framed.as_slice(), CHUNK_SIZE)
+            .read_to_end(&mut received)
+            .unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn test_oversized_chunk_is_refused() {
+        let mut framed = u32::MAX.to_le_bytes().to_vec();
+        framed.extend_from_slice(&[0; 16]);
+
+        let e = StreamFramer::streaming_reader(framed.as_slice(), CHUNK_SIZE)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(
+            TransportError::from(e),
+            TransportError::ChunkTooLarge {
+                size: 0xffff_ffff,
+                max: CHUNK_SIZE
+            }
+        ));
+    }
+}